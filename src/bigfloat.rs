@@ -0,0 +1,292 @@
+use std::fmt;
+use std::str::FromStr;
+
+use num_bigint::{BigInt, BigUint};
+
+/// Arbitrary precision binary floating point number, `mantissa * 2^exponent`.
+///
+/// Additions and multiplications are exact, use `round` to bring the mantissa
+/// back to a given number of bits. Displays as the exact decimal value.
+#[derive(Clone, Debug, Default)]
+pub struct BigFloat {
+    mantissa: BigInt,
+    exponent: i64,
+}
+
+impl BigFloat {
+    pub fn zero() -> Self {
+        Self::default()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa.bits() == 0
+    }
+
+    pub fn from_f64(value: f64) -> Self {
+        if value == 0.0 || !value.is_finite() {
+            return Self::zero();
+        }
+        let bits = value.to_bits();
+        let biased_exponent = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = bits & ((1 << 52) - 1);
+        let (mantissa, exponent) = if biased_exponent == 0 {
+            // subnormal
+            (fraction as i64, -1074)
+        } else {
+            ((fraction | (1 << 52)) as i64, biased_exponent - 1075)
+        };
+        let mantissa = if value < 0.0 { -mantissa } else { mantissa };
+        Self {
+            mantissa: BigInt::from(mantissa),
+            exponent,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let shift = (self.mantissa.bits() as i64 - 62).max(0);
+        let mantissa = i64::try_from(self.truncated_mantissa(shift)).unwrap() as f64;
+        // split the scaling so intermediate powers neither overflow nor underflow
+        let exponent = self.exponent + shift;
+        let half = (exponent / 2).clamp(-1100, 1100) as i32;
+        let rest = (exponent - half as i64).clamp(-1100, 1100) as i32;
+        mantissa * 2f64.powi(half) * 2f64.powi(rest)
+    }
+
+    /// Round towards zero to at most `bits` significant bits.
+    pub fn round(&self, bits: u64) -> Self {
+        let excess = self.mantissa.bits() as i64 - bits as i64;
+        if excess <= 0 {
+            return self.clone();
+        }
+        Self {
+            mantissa: self.truncated_mantissa(excess),
+            exponent: self.exponent + excess,
+        }
+    }
+
+    /// Multiply by `2^exponent`, which is always exact.
+    pub fn mul_pow2(&self, exponent: i64) -> Self {
+        Self {
+            mantissa: self.mantissa.clone(),
+            exponent: self.exponent + exponent,
+        }
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        if self.is_zero() {
+            return other.clone();
+        }
+        if other.is_zero() {
+            return self.clone();
+        }
+        let exponent = self.exponent.min(other.exponent);
+        let a = &self.mantissa << (self.exponent - exponent) as usize;
+        let b = &other.mantissa << (other.exponent - exponent) as usize;
+        Self {
+            mantissa: a + b,
+            exponent,
+        }
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Self {
+            mantissa: &self.mantissa * &other.mantissa,
+            exponent: self.exponent + other.exponent,
+        }
+    }
+
+    pub fn abs(&self) -> Self {
+        if self.is_negative() {
+            self.neg()
+        } else {
+            self.clone()
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa < BigInt::default()
+    }
+
+    pub fn neg(&self) -> Self {
+        Self {
+            mantissa: -&self.mantissa,
+            exponent: self.exponent,
+        }
+    }
+
+    /// Same value with no trailing zero bits in the mantissa.
+    fn normalized(&self) -> Self {
+        match self.mantissa.trailing_zeros() {
+            Some(zeros) => Self {
+                mantissa: &self.mantissa >> zeros as usize,
+                exponent: self.exponent + zeros as i64,
+            },
+            None => Self::zero(),
+        }
+    }
+
+    fn truncated_mantissa(&self, shift: i64) -> BigInt {
+        // shifting a negative BigInt rounds towards negative infinity, keep it symmetric
+        if self.mantissa < BigInt::default() {
+            -((-&self.mantissa) >> shift as usize)
+        } else {
+            &self.mantissa >> shift as usize
+        }
+    }
+}
+
+/// Equal values compare equal whatever the exponent they are kept with.
+impl PartialEq for BigFloat {
+    fn eq(&self, other: &Self) -> bool {
+        self.sub(other).is_zero()
+    }
+}
+
+impl Eq for BigFloat {}
+
+impl fmt::Display for BigFloat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = self.normalized();
+        if value.exponent >= 0 {
+            return write!(f, "{}", value.mantissa << value.exponent as usize);
+        }
+
+        // every binary fraction has a finite decimal expansion, with as many digits as bits
+        let decimals = (-value.exponent) as usize;
+        let scaled = value.mantissa.magnitude() * BigUint::from(5u32).pow(decimals as u32);
+        let digits = format!("{:0>width$}", scaled, width = decimals + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        let sign = if value.mantissa < BigInt::default() {
+            "-"
+        } else {
+            ""
+        };
+        write!(f, "{}{}.{}", sign, integer, fraction)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBigFloatError;
+
+impl fmt::Display for ParseBigFloatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid decimal number")
+    }
+}
+
+impl std::error::Error for ParseBigFloatError {}
+
+/// Parse a decimal number such as `-1.25e-3`. Numbers with no exact binary representation
+/// are rounded to 64 bits more than their decimal digits hold, so the output of `Display`
+/// always reads back exactly.
+impl FromStr for BigFloat {
+    type Err = ParseBigFloatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, exponent) = match s.find(['e', 'E']) {
+            Some(index) => (
+                &s[..index],
+                s[index + 1..]
+                    .parse::<i64>()
+                    .map_err(|_| ParseBigFloatError)?,
+            ),
+            None => (s, 0),
+        };
+        let (sign, number) = match number.strip_prefix('-') {
+            Some(number) => (-1, number),
+            None => (1, number.strip_prefix('+').unwrap_or(number)),
+        };
+        let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let digits = format!("{}{}", integer, fraction);
+        if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            return Err(ParseBigFloatError);
+        }
+
+        let mantissa = sign * BigInt::from_str(&digits).map_err(|_| ParseBigFloatError)?;
+        let exponent = exponent - fraction.len() as i64;
+        // far beyond anything the viewport can show, and slow to compute
+        if exponent.abs() > 100_000 {
+            return Err(ParseBigFloatError);
+        }
+        let power = |exponent: i64| BigInt::from(10).pow(exponent as u32);
+        if exponent >= 0 {
+            return Ok(Self {
+                mantissa: mantissa * power(exponent),
+                exponent: 0,
+            });
+        }
+
+        // dividing by 10^k takes away about as many bits as shifting by k * log2(10) adds
+        let shift = 64 + (-exponent as f64 * std::f64::consts::LOG2_10).ceil() as i64;
+        let divisor = power(-exponent);
+        let half = &divisor / 2;
+        let shifted = mantissa << shift as usize;
+        let rounded = if shifted < BigInt::default() {
+            shifted - half
+        } else {
+            shifted + half
+        };
+        Ok(Self {
+            mantissa: rounded / divisor,
+            exponent: -shift,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_reads_back_exactly() {
+        for source in [
+            "0",
+            "1",
+            "-2.5",
+            "0.1",
+            "-1.25e-3",
+            "123456789e40",
+            "3.14159e-300",
+        ] {
+            let value: BigFloat = source.parse().unwrap();
+            let reparsed: BigFloat = value.to_string().parse().unwrap();
+            assert_eq!(reparsed, value, "{}", source);
+            assert_eq!(reparsed.to_string(), value.to_string(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn parses_decimals() {
+        assert_eq!("1.5".parse::<BigFloat>().unwrap().to_string(), "1.5");
+        assert_eq!("-0.25".parse::<BigFloat>().unwrap().to_string(), "-0.25");
+        assert_eq!("+3e2".parse::<BigFloat>().unwrap().to_string(), "300");
+        assert_eq!("0.1".parse::<BigFloat>().unwrap().to_f64(), 0.1);
+        assert_eq!(BigFloat::from_f64(-0.75).to_string(), "-0.75");
+    }
+
+    #[test]
+    fn rejects_invalid_numbers() {
+        for source in [
+            "",
+            "-",
+            ".",
+            "1.2.3",
+            "abc",
+            "1e",
+            "1e1000000",
+            "0x10",
+            "1 ",
+        ] {
+            assert_eq!(
+                source.parse::<BigFloat>(),
+                Err(ParseBigFloatError),
+                "{}",
+                source
+            );
+        }
+    }
+}
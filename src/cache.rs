@@ -0,0 +1,313 @@
+use crate::histogram::{Histogram, HISTOGRAM_SOURCE};
+use crate::renderer::create_render_pipeline;
+use crate::uniform::{Uniform, COLORING_HISTOGRAM};
+
+/// Must match the `@workgroup_size` of the per pixel passes in `cache.wgsl` and `histogram.wgsl`.
+pub const WORKGROUP_SIZE: u32 = 8;
+
+/// Shader source of the cache passes, appended to the fractal shader.
+const CACHE_SOURCE: &str = include_str!("shaders/cache.wgsl");
+
+/// Format of the point textures, `Point.escape` and `Point.rest` in `shader.wgsl`.
+const POINT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+/// What `cs_iterate` finds for every pixel, kept so that frames where only the colouring changed,
+/// such as while the palette cycles, do not run the escape loop again. Bound after the uniform,
+/// orbit and palette groups.
+///
+/// Needs compute shaders and storage buffers, which WebGL2 does not have.
+pub struct IterationCache {
+    iterate_bind_group_layout: wgpu::BindGroupLayout,
+    color_bind_group_layout: wgpu::BindGroupLayout,
+    iterate_pipeline_layout: wgpu::PipelineLayout,
+    color_pipeline_layout: wgpu::PipelineLayout,
+    iterate_pipeline: wgpu::ComputePipeline,
+    color_pipeline: wgpu::RenderPipeline,
+    /// Format of the textures drawn to, to rebuild `color_pipeline`.
+    format: wgpu::TextureFormat,
+    histogram: Histogram,
+    /// Point textures of the size last rendered, created on first use.
+    target: Option<CacheTarget>,
+    /// Uniform of the last `prepare`, as from `iteration_key`.
+    key: Uniform,
+    /// `key` and size the point textures were last iterated with, `None` after the formula changed.
+    iterated: Option<(Uniform, [u32; 2])>,
+}
+
+/// The point textures with the bind groups writing and reading them.
+struct CacheTarget {
+    size: [u32; 2],
+    _textures: [wgpu::Texture; 2],
+    iterate_bind_group: wgpu::BindGroup,
+    histogram_bind_group: wgpu::BindGroup,
+    color_bind_group: wgpu::BindGroup,
+}
+
+impl IterationCache {
+    /// Whether `device` can run the cache and histogram passes.
+    pub fn is_supported(device: &wgpu::Device) -> bool {
+        let limits = device.limits();
+        limits.max_compute_workgroups_per_dimension > 0
+            && limits.max_storage_buffers_per_shader_stage > 0
+            && limits.max_storage_textures_per_shader_stage >= 2
+    }
+
+    /// `bind_group_layouts` are the groups before the cache's own, `format` that of
+    /// the textures drawn to and `source` the fractal shader, as from `shader_source`.
+    pub fn new(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        format: wgpu::TextureFormat,
+        source: &str,
+    ) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: POINT_FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let iterate_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[storage_entry(0), storage_entry(1)],
+                label: Some("iterate_bind_group_layout"),
+            });
+        let color_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(2),
+                    texture_entry(3),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("color_bind_group_layout"),
+            });
+
+        let pipeline_layout = |label, layout| {
+            let mut layouts = bind_group_layouts.to_vec();
+            layouts.push(layout);
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &layouts,
+                push_constant_ranges: &[],
+            })
+        };
+        let iterate_pipeline_layout =
+            pipeline_layout("Iterate Pipeline Layout", &iterate_bind_group_layout);
+        let color_pipeline_layout =
+            pipeline_layout("Color Pipeline Layout", &color_bind_group_layout);
+
+        let shader = create_shader(device, source);
+        let iterate_pipeline =
+            create_compute_pipeline(device, &iterate_pipeline_layout, &shader, "cs_iterate");
+        let color_pipeline =
+            create_render_pipeline(device, &color_pipeline_layout, format, &shader, "fs_color");
+        let histogram = Histogram::new(device, bind_group_layouts, &shader);
+
+        Self {
+            iterate_bind_group_layout,
+            color_bind_group_layout,
+            iterate_pipeline_layout,
+            color_pipeline_layout,
+            iterate_pipeline,
+            color_pipeline,
+            format,
+            histogram,
+            target: None,
+            key: Uniform::default(),
+            iterated: None,
+        }
+    }
+
+    /// Rebuild the pipelines with the fractal shader `source`, after its custom formula changed.
+    pub fn set_source(&mut self, device: &wgpu::Device, source: &str) {
+        let shader = create_shader(device, source);
+        self.iterate_pipeline =
+            create_compute_pipeline(device, &self.iterate_pipeline_layout, &shader, "cs_iterate");
+        self.color_pipeline = create_render_pipeline(
+            device,
+            &self.color_pipeline_layout,
+            self.format,
+            &shader,
+            "fs_color",
+        );
+        self.histogram.set_shader(device, &shader);
+        self.iterated = None;
+    }
+
+    /// Note the uniform the next frame is drawn with, to iterate again if it changed.
+    pub fn prepare(&mut self, uniform: &Uniform) {
+        self.key = iteration_key(uniform);
+    }
+
+    fn create_target(&self, device: &wgpu::Device, size: [u32; 2]) -> CacheTarget {
+        let textures = ["Point Escape Texture", "Point Rest Texture"].map(|label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size[0],
+                    height: size[1],
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: POINT_FORMAT,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        });
+        let views = textures
+            .each_ref()
+            .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()));
+        let view_entry = |binding, view| wgpu::BindGroupEntry {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        };
+
+        let iterate_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.iterate_bind_group_layout,
+            entries: &[view_entry(0, &views[0]), view_entry(1, &views[1])],
+            label: Some("iterate_bind_group"),
+        });
+        let color_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.color_bind_group_layout,
+            entries: &[
+                view_entry(2, &views[0]),
+                view_entry(3, &views[1]),
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.histogram.buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("color_bind_group"),
+        });
+        CacheTarget {
+            size,
+            _textures: textures,
+            iterate_bind_group,
+            histogram_bind_group: self.histogram.create_bind_group(device, &views[0]),
+            color_bind_group,
+        }
+    }
+
+    /// Record iterating a `width` by `height` target, with `bind_groups` bound as the groups
+    /// before the cache's own, unless the cache already holds it.
+    pub fn compute(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_groups: &[&wgpu::BindGroup],
+        width: u32,
+        height: u32,
+    ) {
+        let iteration = (self.key, [width, height]);
+        if self.iterated == Some(iteration) {
+            return;
+        }
+        if self.target.as_ref().map(|target| target.size) != Some([width, height]) {
+            self.target = Some(self.create_target(device, [width, height]));
+        }
+        let target = self.target.as_ref().expect("target was just created");
+        let equalize = self.key.coloring_mode == COLORING_HISTOGRAM;
+        if equalize {
+            self.histogram.clear(encoder);
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Iterate Pass"),
+            timestamp_writes: None,
+        });
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            compute_pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        let group = bind_groups.len() as u32;
+        compute_pass.set_pipeline(&self.iterate_pipeline);
+        compute_pass.set_bind_group(group, &target.iterate_bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            width.div_ceil(WORKGROUP_SIZE),
+            height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+        if equalize {
+            let bind_group = &target.histogram_bind_group;
+            self.histogram
+                .count(&mut compute_pass, bind_group, group, width, height);
+        }
+        drop(compute_pass);
+        self.iterated = Some(iteration);
+    }
+
+    /// Set the pipeline colouring from the last `compute`, bound after `bind_groups`.
+    pub fn set_color_pipeline<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        bind_groups: &[&'a wgpu::BindGroup],
+    ) {
+        let target = self.target.as_ref().expect("compute creates the target");
+        render_pass.set_pipeline(&self.color_pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            render_pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        render_pass.set_bind_group(bind_groups.len() as u32, &target.color_bind_group, &[]);
+    }
+}
+
+/// `uniform` without the fields only the colouring reads, equal for frames the cache can colour.
+/// The sample offset is kept, frames where only the palette moves keep it as well, see
+/// `Refinement::Cycling`.
+pub fn iteration_key(uniform: &Uniform) -> Uniform {
+    Uniform {
+        time: 0.0,
+        mouse: [0.0, 0.0],
+        palette_period: 0.0,
+        palette_offset: 0.0,
+        ..*uniform
+    }
+}
+
+/// Fractal shader `source` with the cache and histogram passes appended.
+fn create_shader(device: &wgpu::Device, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Cache Shader"),
+        source: wgpu::ShaderSource::Wgsl(
+            format!("{}\n{}\n{}", source, CACHE_SOURCE, HISTOGRAM_SOURCE).into(),
+        ),
+    })
+}
+
+pub fn create_compute_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(layout),
+        module: shader,
+        entry_point,
+    })
+}
@@ -0,0 +1,486 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::bigfloat::BigFloat;
+use crate::cpu;
+use crate::expression::Expression;
+use crate::fractal::{Formula, FractalKind};
+use crate::headless::{HeadlessError, HeadlessRenderer};
+use crate::palette::Palette;
+use crate::polynomial::Polynomial;
+use crate::sampling::{SamplePattern, Sampling, SAMPLES_MAX};
+use crate::scene::Scene;
+use crate::uniform::{
+    COLORING_BANDED, COLORING_DISTANCE, COLORING_HISTOGRAM, COLORING_SMOOTH, COLORING_STRIPE,
+    COLORING_TRAP, COLORING_TRIANGLE, INTERIOR_BLACK, INTERIOR_FINAL_Z, INTERIOR_MULTIPLIER,
+    INTERIOR_PERIOD, MAX_ITERATIONS_LIMIT, MAX_ITERATIONS_MIN, NEWTON_METHOD_HALLEY,
+    NEWTON_METHOD_NEWTON, TRAP_CIRCLE, TRAP_CROSS, TRAP_LINE, TRAP_POINT,
+};
+use crate::viewport::{Viewport, PRECISION_WARNING};
+use crate::window::{self, Options};
+
+/// Explore the Julia and Mandelbrot sets, those of the Burning Ship, Tricorn, Celtic,
+/// Perpendicular and Buffalo formulas, the Newton and Nova fractals, and those of
+/// formulas of your own.
+///
+/// The view fields of the shader uniform (size, rotation, center, exponent and precision
+/// mode) follow from --center, --zoom and --rotation, the mouse position and time follow
+/// the input and the clock.
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub scene: SceneArgs,
+    /// Logical size of the window, as WIDTHxHEIGHT.
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<[u32; 2]>,
+    /// Start in borderless fullscreen on the current monitor.
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Keep the palette still, also implied by --palette-offset, --view and --on-demand.
+    #[arg(long)]
+    pub no_palette_cycling: bool,
+    /// Redraw only when the view changes or animates instead of continuously, to save power.
+    /// The palette starts still, as a cycling one is redrawn on every frame, T starts it.
+    #[arg(long)]
+    pub on_demand: bool,
+    /// Samples averaged in every pixel against aliasing, N takes N by N of them, 1 takes
+    /// one in the center [default: 2]. The window adds one per frame while the view stays still.
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..=SAMPLES_MAX as i64),
+        global = true
+    )]
+    pub samples: Option<u32>,
+    /// Take the samples at random points within their cells of the N by N grid, instead of
+    /// at their centers.
+    #[arg(long, global = true)]
+    pub jitter: bool,
+    /// Stop at the samples of --samples in the window, instead of averaging more while the
+    /// view stays still until it converges.
+    #[arg(long)]
+    pub no_accumulation: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render a PNG image without opening a window and exit.
+    Render {
+        output: PathBuf,
+        /// Size of the image in pixels, as WIDTHxHEIGHT.
+        #[arg(long, value_parser = parse_size, default_value = "1920x1080")]
+        size: [u32; 2],
+    },
+    /// Write the view given by the other options to a parameter file and exit.
+    Save { output: PathBuf },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Coloring {
+    Smooth,
+    Banded,
+    Distance,
+    Trap,
+    Stripe,
+    Triangle,
+    Histogram,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Interior {
+    Black,
+    Period,
+    Multiplier,
+    FinalZ,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TrapShape {
+    Point,
+    Line,
+    Cross,
+    Circle,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum NewtonMethod {
+    Newton,
+    Halley,
+}
+
+/// What to show, shared by the window and `render`.
+#[derive(Args, Debug, Default, PartialEq)]
+pub struct SceneArgs {
+    /// Parameter file, or PNG written by the explorer, to start from. The other options
+    /// change what it holds.
+    #[arg(long, value_parser = parse_view, global = true)]
+    pub view: Option<Scene>,
+    /// Fractal to show [default: julia].
+    #[arg(long, value_enum, global = true)]
+    pub fractal: Option<FractalKind>,
+    /// Julia set parameter, as RE,IM.
+    #[arg(short, value_parser = parse_complex, allow_hyphen_values = true, global = true)]
+    pub c: Option<[f64; 2]>,
+    /// Power the formula raises z to, 3 gives z^3 + c, as RE or RE,IM [default: 2]. Powers
+    /// other than whole numbers from 2 to 16 are drawn in single precision only.
+    #[arg(long, value_parser = parse_power, allow_hyphen_values = true, global = true)]
+    pub power: Option<[f32; 2]>,
+    /// Formula of the custom fractals, z -> f(z, c), such as 'z = z^3 + c*sin(z)'. It may
+    /// use the operators + - * / ^, i and the functions sqrt, exp, log, sin, cos, tan, sinh,
+    /// cosh, tanh, conj, abs, re and im. Shows its Julia sets unless --fractal says otherwise.
+    #[arg(long, allow_hyphen_values = true, global = true)]
+    pub formula: Option<Expression>,
+    /// Polynomial of the Newton and Nova fractals, as comma separated coefficients from
+    /// the highest power down, RE or RE:IM, so 1,0,0,-1 is z^3 - 1 [default: 1,0,0,-1].
+    #[arg(long, allow_hyphen_values = true, global = true)]
+    pub polynomial: Option<Polynomial>,
+    /// Factor of the Newton step, as RE or RE,IM [default: 1].
+    #[arg(long, value_parser = parse_power, allow_hyphen_values = true, global = true)]
+    pub relaxation: Option<[f32; 2]>,
+    /// Root finding step of the Newton and Nova fractals [default: newton].
+    #[arg(long, value_enum, global = true)]
+    pub newton_method: Option<NewtonMethod>,
+    /// Center of the view, as RE,IM, with as many digits as needed.
+    #[arg(long, value_parser = parse_center, allow_hyphen_values = true, global = true)]
+    pub center: Option<[BigFloat; 2]>,
+    /// Magnification with respect to a view 3.1 wide, 1e100 is a deep zoom [default: 1].
+    #[arg(long, value_parser = parse_zoom, global = true)]
+    pub zoom: Option<f64>,
+    /// Counterclockwise rotation of the view in degrees [default: 0].
+    #[arg(long, allow_hyphen_values = true, global = true)]
+    pub rotation: Option<f64>,
+    /// Maximum number of iterations, set from the zoom depth when not given.
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(MAX_ITERATIONS_MIN as i64..=MAX_ITERATIONS_LIMIT as i64),
+        global = true
+    )]
+    pub iterations: Option<u32>,
+    /// How the escape iteration is turned into a colour [default: smooth].
+    #[arg(long, value_enum, global = true)]
+    pub coloring: Option<Coloring>,
+    /// How points that do not escape are coloured: by the period or the multiplier of the
+    /// cycle their orbit is attracted to, or by the final |z| [default: black].
+    #[arg(long, value_enum, global = true)]
+    pub interior: Option<Interior>,
+    /// Shape of the orbit trap of --coloring trap, the line is horizontal and the cross
+    /// adds a vertical one [default: point].
+    #[arg(long, value_enum, global = true)]
+    pub trap: Option<TrapShape>,
+    /// Point the orbit trap is placed at, as RE,IM [default: 0,0].
+    #[arg(long, value_parser = parse_complex, allow_hyphen_values = true, global = true)]
+    pub trap_position: Option<[f64; 2]>,
+    /// Radius of the circle orbit trap [default: 0.5].
+    #[arg(long, value_parser = parse_radius, global = true)]
+    pub trap_radius: Option<f32>,
+    /// Stripes per turn of --coloring stripe, whole numbers have no seam [default: 4].
+    #[arg(long, value_parser = parse_density, global = true)]
+    pub stripe_density: Option<f32>,
+    /// Name of a built-in palette or path to a .ggr, .map or .json file.
+    #[arg(long, value_parser = parse_palette, global = true)]
+    pub palette: Option<Palette>,
+    /// Iterations it takes to go once through the palette.
+    #[arg(long, value_parser = parse_period, global = true)]
+    pub palette_period: Option<f32>,
+    /// Shift of the palette, from 0.0 to 1.0.
+    #[arg(long, value_parser = parse_offset, allow_hyphen_values = true, global = true)]
+    pub palette_offset: Option<f32>,
+}
+
+impl SceneArgs {
+    pub fn to_scene(&self) -> Scene {
+        let mut scene = self.view.clone().unwrap_or_default();
+        let (viewport, uniform) = (&mut scene.viewport, &mut scene.uniform);
+        // before the fractal, the Nova plane starts from a root of the polynomial
+        if let Some(polynomial) = &self.polynomial {
+            uniform.set_polynomial(polynomial);
+        }
+        if let Some(formula) = &self.formula {
+            scene.formula = formula.clone();
+            uniform.set_formula(formula);
+        }
+        let fractal = self.fractal.or_else(|| {
+            // a formula is only drawn by the custom kinds
+            let custom = FractalKind::new(Formula::Custom, uniform.get_fractal_kind().is_julia());
+            self.formula.is_some().then_some(custom)
+        });
+        if let Some(fractal_kind) = fractal {
+            uniform.set_fractal_kind(fractal_kind);
+            if self.view.is_none() {
+                // same as the keys
+                uniform.reset_c(fractal_kind);
+                if !fractal_kind.is_julia() {
+                    viewport.set_center(fractal_kind.get_formula().get_center());
+                }
+            }
+        }
+        if let Some(power) = self.power {
+            uniform.power = power;
+        }
+        if let Some(relaxation) = self.relaxation {
+            uniform.relaxation = relaxation;
+        }
+        match self.newton_method {
+            Some(NewtonMethod::Newton) => uniform.newton_method = NEWTON_METHOD_NEWTON,
+            Some(NewtonMethod::Halley) => uniform.newton_method = NEWTON_METHOD_HALLEY,
+            None => {}
+        }
+        if let Some(center) = &self.center {
+            viewport.center = center.clone();
+        }
+        if let Some(zoom) = self.zoom {
+            viewport.scale = Viewport::default().scale / zoom;
+        }
+        if let Some(rotation) = self.rotation {
+            viewport.rotation = 0.0;
+            viewport.rotate(rotation.to_radians());
+        }
+
+        if let Some(max_iterations) = self.iterations {
+            uniform.max_iterations = max_iterations;
+            scene.max_iterations_auto = false;
+        }
+        match self.coloring {
+            Some(Coloring::Banded) => uniform.coloring_mode = COLORING_BANDED,
+            Some(Coloring::Smooth) => uniform.coloring_mode = COLORING_SMOOTH,
+            Some(Coloring::Distance) => uniform.coloring_mode = COLORING_DISTANCE,
+            Some(Coloring::Trap) => uniform.coloring_mode = COLORING_TRAP,
+            Some(Coloring::Stripe) => uniform.coloring_mode = COLORING_STRIPE,
+            Some(Coloring::Triangle) => uniform.coloring_mode = COLORING_TRIANGLE,
+            Some(Coloring::Histogram) => uniform.coloring_mode = COLORING_HISTOGRAM,
+            None => {}
+        }
+        match self.interior {
+            Some(Interior::Black) => uniform.interior_mode = INTERIOR_BLACK,
+            Some(Interior::Period) => uniform.interior_mode = INTERIOR_PERIOD,
+            Some(Interior::Multiplier) => uniform.interior_mode = INTERIOR_MULTIPLIER,
+            Some(Interior::FinalZ) => uniform.interior_mode = INTERIOR_FINAL_Z,
+            None => {}
+        }
+        match self.trap {
+            Some(TrapShape::Point) => uniform.trap_shape = TRAP_POINT,
+            Some(TrapShape::Line) => uniform.trap_shape = TRAP_LINE,
+            Some(TrapShape::Cross) => uniform.trap_shape = TRAP_CROSS,
+            Some(TrapShape::Circle) => uniform.trap_shape = TRAP_CIRCLE,
+            None => {}
+        }
+        if let Some(trap_position) = self.trap_position {
+            uniform.trap_position = trap_position.map(|part| part as f32);
+        }
+        if let Some(trap_radius) = self.trap_radius {
+            uniform.trap_radius = trap_radius;
+        }
+        if let Some(stripe_density) = self.stripe_density {
+            uniform.stripe_density = stripe_density;
+        }
+        if let Some(palette_period) = self.palette_period {
+            uniform.palette_period = palette_period;
+        }
+        if let Some(palette_offset) = self.palette_offset {
+            uniform.palette_offset = palette_offset;
+        }
+        if let Some(palette) = &self.palette {
+            scene.palette = palette.clone();
+        }
+        if let Some(c) = self.c {
+            scene.set_c(c);
+        }
+        scene
+    }
+}
+
+impl Cli {
+    /// Open the window, or render an image when asked to.
+    pub fn run(self) {
+        let scene = self.scene.to_scene();
+        let sampling = Sampling {
+            pattern: if self.jitter {
+                SamplePattern::Jittered
+            } else {
+                SamplePattern::Grid
+            },
+            count: self.samples.unwrap_or(Sampling::default().count),
+        };
+        match self.command {
+            Some(Command::Render { output, size }) => {
+                env_logger::init();
+                if let Err(error) = render(&output, &scene, size, sampling) {
+                    eprintln!("error: could not render {}: {}", output.display(), error);
+                    std::process::exit(1);
+                }
+            }
+            Some(Command::Save { output }) => {
+                if let Err(error) = scene.save(&output) {
+                    eprintln!("error: could not save {}: {}", output.display(), error);
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                let options = Options {
+                    size: self.size,
+                    fullscreen: self.fullscreen,
+                    // without arguments the window fits the default view to its shape
+                    scene: (self.scene != SceneArgs::default()).then_some(scene),
+                    palette_cycling: !self.no_palette_cycling
+                        && !self.on_demand
+                        && self.scene.palette_offset.is_none()
+                        && self.scene.view.is_none(),
+                    redraw_on_demand: self.on_demand,
+                    sampling,
+                    accumulating: !self.no_accumulation,
+                };
+                if let Err(error) = pollster::block_on(window::run_with(options)) {
+                    eprintln!("error: could not open a window: {}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// Render `scene` to a PNG file, on the CPU if there is no graphics adapter.
+fn render(
+    path: &Path,
+    scene: &Scene,
+    [width, height]: [u32; 2],
+    sampling: Sampling,
+) -> Result<(), HeadlessError> {
+    let mut viewport = scene.viewport.clone();
+    viewport.set_aspect_ratio(width as f64 / height as f64);
+    if viewport.is_beyond_precision(&scene.get_uniform()) {
+        log::warn!("{}", PRECISION_WARNING);
+    }
+    match pollster::block_on(HeadlessRenderer::new(&scene.palette)) {
+        Ok(mut renderer) => {
+            renderer.set_sampling(sampling);
+            renderer.render_png(path, scene, width, height)
+        }
+        Err(HeadlessError::NoAdapter) => {
+            log::warn!("no usable graphics adapter, rendering on the CPU");
+            let uniform = scene.get_uniform();
+            let (viewport, palette, formula) = (&scene.viewport, &scene.palette, &scene.formula);
+            let image = cpu::render_samples(
+                viewport, &uniform, formula, palette, width, height, &sampling,
+            );
+            scene.save_png(path, &image)?;
+            Ok(())
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn parse_size(value: &str) -> Result<[u32; 2], String> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {}", value))?;
+    let parse = |value: &str| match value.trim().parse::<u32>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(format!("invalid size {}", value)),
+    };
+    Ok([parse(width)?, parse(height)?])
+}
+
+fn parse_zoom(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(zoom) if zoom > 0.0 && zoom.is_finite() => Ok(zoom),
+        Ok(_) => Err(format!("zoom must be positive, got {}", value)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn parse_radius(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(radius) if radius >= 0.0 && radius.is_finite() => Ok(radius),
+        Ok(_) => Err(format!("radius must not be negative, got {}", value)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn parse_density(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(density) if density > 0.0 && density.is_finite() => Ok(density),
+        Ok(_) => Err(format!("density must be positive, got {}", value)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn parse_period(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(period) if period > 0.0 && period.is_finite() => Ok(period),
+        Ok(_) => Err(format!("period must be positive and finite, got {}", value)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn parse_offset(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(offset) if offset.is_finite() => Ok(offset),
+        Ok(_) => Err(format!("offset must be finite, got {}", value)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn parse_complex(value: &str) -> Result<[f64; 2], String> {
+    let (re, im) = value
+        .split_once(',')
+        .ok_or_else(|| format!("expected RE,IM, got {}", value))?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<f64>()
+            .map_err(|error| format!("{}: {}", value, error))
+    };
+    Ok([parse(re)?, parse(im)?])
+}
+
+fn parse_power(value: &str) -> Result<[f32; 2], String> {
+    let (re, im) = value.split_once(',').unwrap_or((value, "0"));
+    let parse = |value: &str| match value.trim().parse::<f32>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        Ok(_) => Err(format!("must be finite, got {}", value)),
+        Err(error) => Err(format!("{}: {}", value, error)),
+    };
+    Ok([parse(re)?, parse(im)?])
+}
+
+fn parse_center(value: &str) -> Result<[BigFloat; 2], String> {
+    let (re, im) = value
+        .split_once(',')
+        .ok_or_else(|| format!("expected RE,IM, got {}", value))?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<BigFloat>()
+            .map_err(|error| format!("{}: {}", value, error))
+    };
+    Ok([parse(re)?, parse(im)?])
+}
+
+fn parse_view(value: &str) -> Result<Scene, String> {
+    Scene::load(Path::new(value)).map_err(|error| error.to_string())
+}
+
+fn parse_palette(value: &str) -> Result<Palette, String> {
+    let library = Palette::library();
+    if let Some(palette) = library
+        .into_iter()
+        .find(|palette| palette.name.eq_ignore_ascii_case(value))
+    {
+        return Ok(palette);
+    }
+    let names: Vec<_> = Palette::library()
+        .into_iter()
+        .map(|palette| palette.name)
+        .collect();
+    Palette::load(Path::new(value)).map_err(|error| {
+        format!(
+            "{} is neither a built-in palette ({}) nor a palette file: {}",
+            value,
+            names.join(", "),
+            error
+        )
+    })
+}
@@ -0,0 +1,1101 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use crate::bigfloat::BigFloat;
+use crate::expression::{Expression, Function, Node, Operator};
+use crate::fractal::Formula;
+use crate::histogram::HISTOGRAM_BINS;
+use crate::palette::{Palette, PALETTE_WIDTH};
+use crate::perturbation::{ReferenceOrbit, BAILOUT_SQUARED};
+use crate::sampling::Sampling;
+use crate::uniform::{
+    Uniform, COLORING_BANDED, COLORING_DISTANCE, COLORING_HISTOGRAM, COLORING_STRIPE,
+    COLORING_TRAP, COLORING_TRIANGLE, INTERIOR_BLACK, INTERIOR_FINAL_Z, INTERIOR_PERIOD,
+    NEWTON_METHOD_HALLEY, PRECISION_DOUBLE_SINGLE, PRECISION_PERTURBATION, TRAP_CIRCLE, TRAP_CROSS,
+    TRAP_LINE,
+};
+use crate::viewport::Viewport;
+
+/// Must match `BAILOUT_SQUARED_BANDED` in `shader.wgsl`.
+const BAILOUT_SQUARED_BANDED: f32 = 4.0;
+
+/// Must match the constants of the same names in `shader.wgsl`.
+const NEWTON_TOLERANCE_SQUARED: f32 = 1e-8;
+const NEWTON_SHADING: f32 = 16.0;
+const TRAP_DISTANCE_MAX: f32 = 1e30;
+const TRAP_PALETTE_PERIOD: f32 = 4.0;
+const PERIOD_TOLERANCE_SQUARED: f32 = 1e-12;
+const INTERIOR_PERIOD_STEP: f32 = 0.618034;
+const PERIOD_RETURN_FACTOR: f32 = 1e4;
+
+/// Render on the CPU what the shader renders on the GPU, used when there is no graphics adapter
+/// and to check the GPU output. Arguments are the same as `HeadlessRenderer::render`, with
+/// the custom `formula` the GPU has compiled into its pipeline.
+///
+/// Single precision matches the shader step by step, emulated double precision is done in `f64`.
+pub fn render(
+    viewport: &Viewport,
+    uniform: &Uniform,
+    formula: &Expression,
+    palette: &Palette,
+    width: u32,
+    height: u32,
+) -> image::RgbaImage {
+    let mut viewport = viewport.clone();
+    viewport.set_aspect_ratio(width as f64 / height as f64);
+    let mut uniform = *uniform;
+    viewport.write_uniform(&mut uniform);
+
+    let orbit = if uniform.precision_mode == PRECISION_PERTURBATION {
+        let c = uniform.get_c();
+        let c = [BigFloat::from_f64(c[0]), BigFloat::from_f64(c[1])];
+        let (center, bits) = (&viewport.center, viewport.get_precision());
+        let fractal_kind = uniform.get_fractal_kind();
+        let formula = fractal_kind.get_formula();
+        let power = uniform.get_integer_power().unwrap_or(2);
+        let max_iterations = uniform.max_iterations;
+        let orbit = if fractal_kind.is_julia() {
+            ReferenceOrbit::compute(formula, power, center, &c, max_iterations, bits)
+        } else {
+            ReferenceOrbit::compute(formula, power, &c, center, max_iterations, bits)
+        };
+        uniform.reference_length = orbit.len();
+        orbit.points
+    } else {
+        Vec::new()
+    };
+
+    let shader = Shader {
+        uniform,
+        formula: uniform.get_fractal_kind().get_formula(),
+        power: uniform.get_integer_power().unwrap_or(0),
+        custom_formula: formula.get_root(),
+        pixel_size: uniform.size[0] / width as f32,
+        orbit: &orbit,
+        palette: &palette.to_rgba8(),
+        width,
+        height,
+    };
+
+    // rows are handed out in turns so every thread gets a share of the slow ones
+    let mut pixels = vec![0; (width * height * 4) as usize];
+    let mut iterations = vec![0.0; (width * height) as usize];
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let mut rows_per_thread: Vec<Vec<_>> = (0..threads).map(|_| Vec::new()).collect();
+    let rows = pixels.chunks_mut((width * 4) as usize);
+    for (y, (row, row_iterations)) in rows.zip(iterations.chunks_mut(width as usize)).enumerate() {
+        rows_per_thread[y % threads].push((y as u32, row, row_iterations));
+    }
+    std::thread::scope(|scope| {
+        for rows in rows_per_thread {
+            let shader = &shader;
+            scope.spawn(move || {
+                for (y, row, row_iterations) in rows {
+                    let pixels = row.chunks_exact_mut(4).zip(row_iterations);
+                    for (x, (pixel, iterations)) in pixels.enumerate() {
+                        let ([r, g, b], point_iterations) = shader.get_point(x as u32, y);
+                        pixel.copy_from_slice(&[r, g, b, 255]);
+                        *iterations = point_iterations;
+                    }
+                }
+            });
+        }
+    });
+    if uniform.coloring_mode == COLORING_HISTOGRAM {
+        shader.equalize(&mut pixels, &iterations);
+    }
+
+    image::RgbaImage::from_raw(width, height, pixels).expect("image size matches")
+}
+
+/// `render` with the samples of `sampling` taken in every pixel and averaged,
+/// as `HeadlessRenderer::render` does.
+pub fn render_samples(
+    viewport: &Viewport,
+    uniform: &Uniform,
+    formula: &Expression,
+    palette: &Palette,
+    width: u32,
+    height: u32,
+    sampling: &Sampling,
+) -> image::RgbaImage {
+    let mut sums = vec![0.0; (width * height * 4) as usize];
+    for index in 0..sampling.get_samples() {
+        let uniform = Uniform {
+            sample_offset: sampling.get_offset(index),
+            ..*uniform
+        };
+        let image = render(viewport, &uniform, formula, palette, width, height);
+        for (sum, value) in sums.iter_mut().zip(image.as_raw()) {
+            *sum += *value as f32;
+        }
+    }
+    let samples = sampling.get_samples() as f32;
+    let pixels = sums
+        .iter()
+        .map(|sum| (sum / samples).round() as u8)
+        .collect();
+    image::RgbaImage::from_raw(width, height, pixels).expect("image size matches")
+}
+
+/// Same as `Escape` in `shader.wgsl`.
+struct Escape {
+    fraction: f32,
+    distance: f32,
+    trap: f32,
+    interior: [f32; 2],
+    average: f32,
+}
+
+/// Same as `Average` in `shader.wgsl`.
+#[derive(Default)]
+struct Average {
+    sum: f32,
+    last: f32,
+    count: f32,
+}
+
+/// Same as `FLOAT_EXP_ZERO` in `shader.wgsl`.
+const FLOAT_EXP_ZERO: i32 = -100_000;
+
+/// Complex number with a separate exponent, `m * 2^e`, as `FloatExp` in `shader.wgsl`, for
+/// derivatives that grow beyond the range of floats.
+#[derive(Clone, Copy)]
+struct FloatExp {
+    m: [f64; 2],
+    e: i32,
+}
+
+impl FloatExp {
+    fn new(m: [f64; 2], e: i32) -> Self {
+        let m_max = m[0].abs().max(m[1].abs());
+        if m_max == 0.0 {
+            return Self {
+                m: [0.0, 0.0],
+                e: FLOAT_EXP_ZERO,
+            };
+        }
+        let k = m_max.log2().floor() as i32;
+        Self {
+            m: m.map(|part| ldexp(part, -k)),
+            e: e + k,
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        let e = self.e.max(other.e);
+        // anything shifted further than this is below f64 precision anyway
+        let shift = |a: Self| a.m.map(|part| ldexp(part, (a.e - e).max(-1100)));
+        Self::new(add(shift(self), shift(other)), e)
+    }
+
+    fn log2_abs(self) -> f64 {
+        self.e as f64 + 0.5 * (self.m[0] * self.m[0] + self.m[1] * self.m[1]).log2()
+    }
+}
+
+/// `value * 2^exponent`, scaling in two steps so that the power neither overflows nor
+/// underflows on its own.
+fn ldexp(value: f64, exponent: i32) -> f64 {
+    let half = exponent / 2;
+    value * 2f64.powi(half) * 2f64.powi(exponent - half)
+}
+
+/// The functions of `shader.wgsl` with the same names, for a single frame.
+struct Shader<'a> {
+    uniform: Uniform,
+    formula: Formula,
+    /// Same as `get_integer_power`.
+    power: u32,
+    custom_formula: &'a Node,
+    /// Same as `pixel_size`.
+    pixel_size: f32,
+    orbit: &'a [[f32; 2]],
+    palette: &'a [[u8; 4]],
+    width: u32,
+    height: u32,
+}
+
+impl Shader<'_> {
+    /// Colour of the center of pixel `x`, `y` as `get_point_color` in the shader, with
+    /// the iterations it took to escape as `get_point_iterations` for histogram colouring.
+    fn get_point(&self, x: u32, y: u32) -> ([u8; 3], f32) {
+        let uniform = &self.uniform;
+        let julia = uniform.get_fractal_kind().is_julia();
+        // same as position_xy at the sample of the pixel
+        let sample = uniform.sample_offset;
+        let position = [
+            (x as f32 + 0.5 + sample[0]) / self.width as f32 * 2.0 - 1.0,
+            1.0 - (y as f32 + 0.5 + sample[1]) / self.height as f32 * 2.0,
+        ];
+        let offset_unrotated = [
+            position[0] * uniform.size[0] / 2.0,
+            position[1] * uniform.size[1] / 2.0,
+        ];
+        let offset = [
+            offset_unrotated[0] * uniform.rotation[0] - offset_unrotated[1] * uniform.rotation[1],
+            offset_unrotated[0] * uniform.rotation[1] + offset_unrotated[1] * uniform.rotation[0],
+        ];
+
+        let escape = if uniform.precision_mode == PRECISION_PERTURBATION {
+            let scale = 2f64.powi(uniform.exponent);
+            let d = [offset[0] as f64 * scale, offset[1] as f64 * scale];
+            let (unit, zero) = ([scale, 0.0], [0.0, 0.0]);
+            if julia {
+                self.julia_perturbation(d, zero, unit, zero)
+            } else {
+                self.julia_perturbation(zero, d, zero, unit)
+            }
+        } else if uniform.precision_mode == PRECISION_DOUBLE_SINGLE {
+            let scale = 2f64.powi(uniform.exponent);
+            let z = [
+                uniform.center[0] as f64 + uniform.center_lo[0] as f64 + offset[0] as f64 * scale,
+                uniform.center[1] as f64 + uniform.center_lo[1] as f64 + offset[1] as f64 * scale,
+            ];
+            let c = uniform.get_c();
+            let (unit, zero) = ([scale, 0.0], [0.0, 0.0]);
+            if julia {
+                self.julia_double(z, c, unit, zero)
+            } else {
+                self.julia_double(c, z, zero, unit)
+            }
+        } else {
+            let scale = 2f32.powi(uniform.exponent);
+            let z = [
+                uniform.center[0] + offset[0] * scale,
+                uniform.center[1] + offset[1] * scale,
+            ];
+            if self.formula == Formula::Newton {
+                let result = if julia {
+                    self.newton(z, uniform.c)
+                } else {
+                    self.newton(uniform.c, z)
+                };
+                return (self.get_newton_color(result), -1.0);
+            }
+            let (unit, zero) = ([scale, 0.0], [0.0, 0.0]);
+            if julia {
+                self.julia(z, uniform.c, unit, zero)
+            } else {
+                self.julia(uniform.c, z, zero, unit)
+            }
+        };
+
+        let iterations = if uniform.coloring_mode == COLORING_HISTOGRAM && escape.fraction < 1.0 {
+            escape.fraction * uniform.max_iterations as f32
+        } else {
+            -1.0
+        };
+        (self.get_escape_color(escape), iterations)
+    }
+
+    /// Recolour the escaped points of `pixels` as the histogram passes of the shader do,
+    /// `iterations` of each as from `get_point`.
+    fn equalize(&self, pixels: &mut [u8], iterations: &[f32]) {
+        let escaped = || iterations.iter().copied().filter(|&value| value >= 0.0);
+        let low = escaped().fold(f32::INFINITY, f32::min);
+        let high = escaped().fold(0.0, f32::max);
+        let get_bin = |value: f32| {
+            let position =
+                ((value - low) / (high - low).max(1e-30)).clamp(0.0, 1.0) * HISTOGRAM_BINS as f32;
+            let bin = position.floor().min((HISTOGRAM_BINS - 1) as f32);
+            (bin as usize, position - bin)
+        };
+
+        let mut bins = vec![0u32; HISTOGRAM_BINS as usize];
+        for value in escaped() {
+            bins[get_bin(value).0] += 1;
+        }
+        let mut total = 0;
+        for count in &mut bins {
+            total += *count;
+            *count = total;
+        }
+
+        for (pixel, &value) in pixels.chunks_exact_mut(4).zip(iterations) {
+            if value < 0.0 {
+                continue;
+            }
+            let (bin, part) = get_bin(value);
+            let below = if bin > 0 { bins[bin - 1] } else { 0 };
+            let share = (below as f32 + (bins[bin] - below) as f32 * part) / total as f32;
+            let position = (share + self.uniform.palette_offset).rem_euclid(1.0);
+            let color = self
+                .sample_palette(position)
+                .map(|value| value.round() as u8);
+            pixel[..3].copy_from_slice(&color);
+        }
+    }
+
+    fn get_bailout_squared(&self) -> f32 {
+        if self.uniform.coloring_mode == COLORING_BANDED {
+            BAILOUT_SQUARED_BANDED
+        } else {
+            BAILOUT_SQUARED as f32
+        }
+    }
+
+    fn get_fraction(&self, i: i32, z_norm_squared: f32) -> f32 {
+        let iterations_max = self.uniform.max_iterations as i32;
+        if i >= iterations_max {
+            return 1.0;
+        }
+        if self.uniform.coloring_mode == COLORING_BANDED {
+            return i as f32 / iterations_max as f32;
+        }
+        let iterations = (i as f32 - self.get_iteration_excess(z_norm_squared)).max(0.0);
+        iterations / iterations_max as f32
+    }
+
+    fn get_iteration_excess(&self, z_norm_squared: f32) -> f32 {
+        let degree = if self.formula == Formula::Custom {
+            self.uniform.formula_degree
+        } else {
+            self.uniform.power[0]
+        };
+        if degree <= 1.0 {
+            return 0.0;
+        }
+        let ratio = z_norm_squared.ln() / (BAILOUT_SQUARED as f32).ln();
+        ratio.log2() / degree.log2()
+    }
+
+    fn is_averaging(&self) -> bool {
+        let mode = self.uniform.coloring_mode;
+        mode == COLORING_STRIPE || mode == COLORING_TRIANGLE
+    }
+
+    fn add_average_term(&self, average: &mut Average, z: [f32; 2], c: [f32; 2]) {
+        let term = if self.uniform.coloring_mode == COLORING_STRIPE {
+            0.5 + 0.5 * (self.uniform.stripe_density * z[1].atan2(z[0])).sin()
+        } else {
+            let w_norm = (z[0] - c[0]).hypot(z[1] - c[1]);
+            let c_norm = c[0].hypot(c[1]);
+            let (low, high) = ((w_norm - c_norm).abs(), w_norm + c_norm);
+            if high <= low {
+                return;
+            }
+            (z[0].hypot(z[1]) - low) / (high - low)
+        };
+        average.sum += term;
+        average.last = term;
+        average.count += 1.0;
+    }
+
+    fn get_average(&self, average: &Average, z_norm_squared: f32) -> f32 {
+        if average.count < 2.0 {
+            return average.sum;
+        }
+        let last = average.sum / average.count;
+        let before = (average.sum - average.last) / (average.count - 1.0);
+        let t = self.get_iteration_excess(z_norm_squared).clamp(0.0, 1.0);
+        last + (before - last) * t
+    }
+
+    fn get_escape(
+        &self,
+        i: i32,
+        z_norm_squared: f32,
+        derivative_log2: f32,
+        trap: f32,
+        interior: [f32; 2],
+        average: &Average,
+    ) -> Escape {
+        let fraction = self.get_fraction(i, z_norm_squared);
+        if self.uniform.coloring_mode != COLORING_DISTANCE || fraction >= 1.0 {
+            return Escape {
+                fraction,
+                distance: 1.0,
+                trap,
+                interior,
+                average: self.get_average(average, z_norm_squared),
+            };
+        }
+        let z_log = 0.5 * z_norm_squared.ln();
+        let distance_log2 = 0.5 * z_norm_squared.log2() + (0.5 * z_log).log2() - derivative_log2;
+        Escape {
+            fraction,
+            distance: (distance_log2 - self.pixel_size.log2()).exp2(),
+            trap,
+            interior,
+            average: 0.0,
+        }
+    }
+
+    fn get_period_tolerance_squared(&self) -> f32 {
+        let pixel = self.pixel_size * 2f32.powi(self.uniform.exponent);
+        PERIOD_TOLERANCE_SQUARED.min(pixel * pixel)
+    }
+
+    fn get_interior(&self, z: [f32; 2], c: [f32; 2], period: i32) -> [f32; 2] {
+        let uniform = &self.uniform;
+        if uniform.interior_mode == INTERIOR_FINAL_Z {
+            return [(0.5 * z[0].hypot(z[1])).rem_euclid(1.0), 1.0];
+        }
+        if period == 0 || uniform.interior_mode == INTERIOR_BLACK {
+            return [0.0, 0.0];
+        }
+
+        let custom = self.formula == Formula::Custom;
+        let tolerance_squared = PERIOD_RETURN_FACTOR * self.get_period_tolerance_squared();
+        let mut w = z;
+        let mut multiplier = [1.0, 0.0];
+        let mut cycle = period;
+        for k in 1..=period {
+            if custom {
+                w = evaluate(self.custom_formula, w, c);
+            } else {
+                let w_power = self.apply_power(formula_before(self.formula, w));
+                multiplier =
+                    formula_derivative(self.formula, uniform.power, w, w_power, multiplier);
+                w = add(self.apply_formula(w), c);
+            }
+            let d = [w[0] - z[0], w[1] - z[1]];
+            if period % k == 0 && d[0] * d[0] + d[1] * d[1] < tolerance_squared {
+                cycle = k;
+                break;
+            }
+        }
+
+        if uniform.interior_mode == INTERIOR_PERIOD {
+            let position = ((cycle - 1) as f32 * INTERIOR_PERIOD_STEP).rem_euclid(1.0);
+            return [position, 1.0];
+        }
+        if custom {
+            return [0.0, 0.0];
+        }
+        let angle = multiplier[1].atan2(multiplier[0]) / std::f32::consts::TAU;
+        [
+            angle.rem_euclid(1.0),
+            multiplier[0].hypot(multiplier[1]).min(1.0),
+        ]
+    }
+
+    fn get_trap_distance(&self, [x, y]: [f32; 2]) -> f32 {
+        let [dx, dy] = [
+            x - self.uniform.trap_position[0],
+            y - self.uniform.trap_position[1],
+        ];
+        match self.uniform.trap_shape {
+            TRAP_LINE => dy.abs(),
+            TRAP_CROSS => dx.abs().min(dy.abs()),
+            TRAP_CIRCLE => (dx.hypot(dy) - self.uniform.trap_radius).abs(),
+            _ => dx.hypot(dy),
+        }
+    }
+
+    fn julia(
+        &self,
+        z_start: [f32; 2],
+        c: [f32; 2],
+        derivative_start: [f32; 2],
+        derivative_c: [f32; 2],
+    ) -> Escape {
+        let iterations_max = self.uniform.max_iterations as i32;
+        let bailout_squared = self.get_bailout_squared();
+        let custom = self.formula == Formula::Custom;
+        let distance = self.uniform.coloring_mode == COLORING_DISTANCE;
+        let averaging = self.is_averaging();
+        let [mut x, mut y] = z_start;
+        let mut derivative = FloatExp::new(derivative_start.map(|part| part as f64), 0);
+        let derivative_c = FloatExp::new(derivative_c.map(|part| part as f64), 0);
+        let mut trap = TRAP_DISTANCE_MAX;
+        let mut average = Average::default();
+        let tolerance_squared = self.get_period_tolerance_squared();
+        let mut check = z_start;
+        let mut check_i = 0;
+        let mut period = 0;
+        let mut i = 0;
+        while i < iterations_max {
+            if averaging && i > 0 {
+                self.add_average_term(&mut average, [x, y], c);
+            }
+            if x * x + y * y > bailout_squared {
+                break;
+            }
+            if i > 0 {
+                trap = trap.min(self.get_trap_distance([x, y]));
+            }
+            let d = [x - check[0], y - check[1]];
+            if i > 0 && d[0] * d[0] + d[1] * d[1] < tolerance_squared {
+                period = i - check_i;
+                i = iterations_max;
+                break;
+            }
+            if i & (i - 1) == 0 {
+                check = [x, y];
+                check_i = i;
+            }
+            if custom {
+                [x, y] = evaluate(self.custom_formula, [x, y], c);
+            } else {
+                if distance {
+                    let power = self.uniform.power;
+                    let w = self.apply_power(formula_before(self.formula, [x, y]));
+                    let m = derivative.m.map(|part| part as f32);
+                    let d = formula_derivative(self.formula, power, [x, y], w, m);
+                    derivative =
+                        FloatExp::new(d.map(|part| part as f64), derivative.e).add(derivative_c);
+                }
+                let [re, im] = self.apply_formula([x, y]);
+                (x, y) = (re + c[0], im + c[1]);
+            }
+            i += 1;
+        }
+
+        if custom {
+            return Escape {
+                fraction: self.get_fraction(i, x * x + y * y),
+                distance: 1.0,
+                trap,
+                interior: self.get_interior([x, y], c, period),
+                average: self.get_average(&average, x * x + y * y),
+            };
+        }
+        let derivative_log2 = derivative.log2_abs() as f32;
+        let interior = self.get_interior([x, y], c, period);
+        self.get_escape(i, x * x + y * y, derivative_log2, trap, interior, &average)
+    }
+
+    /// Same as `julia_double_single`, with the mantissa of the derivative in `f64` as well.
+    fn julia_double(
+        &self,
+        z_start: [f64; 2],
+        c: [f64; 2],
+        derivative_start: [f64; 2],
+        derivative_c: [f64; 2],
+    ) -> Escape {
+        let iterations_max = self.uniform.max_iterations as i32;
+        let bailout_squared = self.get_bailout_squared() as f64;
+        let distance = self.uniform.coloring_mode == COLORING_DISTANCE;
+        let averaging = self.is_averaging();
+        let [mut x, mut y] = z_start;
+        let mut derivative = FloatExp::new(derivative_start, 0);
+        let derivative_c = FloatExp::new(derivative_c, 0);
+        let mut trap = TRAP_DISTANCE_MAX;
+        let mut average = Average::default();
+        let tolerance_squared = self.get_period_tolerance_squared() as f64;
+        let mut check = z_start;
+        let mut check_i = 0;
+        let mut period = 0;
+        let mut i = 0;
+        while i < iterations_max {
+            if averaging && i > 0 {
+                let c = c.map(|part| part as f32);
+                self.add_average_term(&mut average, [x as f32, y as f32], c);
+            }
+            if x * x + y * y > bailout_squared {
+                break;
+            }
+            if i > 0 {
+                trap = trap.min(self.get_trap_distance([x as f32, y as f32]));
+            }
+            let d = [x - check[0], y - check[1]];
+            if i > 0 && d[0] * d[0] + d[1] * d[1] < tolerance_squared {
+                period = i - check_i;
+                i = iterations_max;
+                break;
+            }
+            if i & (i - 1) == 0 {
+                check = [x, y];
+                check_i = i;
+            }
+            let before = formula_before(self.formula, [x, y]);
+            let w = complex_pow(before, self.power);
+            if distance {
+                let power = self.uniform.power.map(|part| part as f64);
+                let d = formula_derivative(self.formula, power, [x, y], w, derivative.m);
+                derivative = FloatExp::new(d, derivative.e).add(derivative_c);
+            }
+            let [re, im] = formula_after(self.formula, w);
+            (x, y) = (re + c[0], im + c[1]);
+            i += 1;
+        }
+        let derivative_log2 = derivative.log2_abs();
+        let (z, c) = ([x as f32, y as f32], c.map(|part| part as f32));
+        let interior = self.get_interior(z, c, period);
+        self.get_escape(
+            i,
+            (x * x + y * y) as f32,
+            derivative_log2 as f32,
+            trap,
+            interior,
+            &average,
+        )
+    }
+
+    /// `f64` has enough range for the differences and the derivative, so no separate exponent
+    /// is needed. There is no periodicity check, as in the shader.
+    fn julia_perturbation(
+        &self,
+        dz_start: [f64; 2],
+        dc: [f64; 2],
+        derivative_start: [f64; 2],
+        derivative_c: [f64; 2],
+    ) -> Escape {
+        let iterations_max = self.uniform.max_iterations as i32;
+        let bailout_squared = self.get_bailout_squared();
+        let distance = self.uniform.coloring_mode == COLORING_DISTANCE;
+        let averaging = self.is_averaging();
+        let c_reference = if self.uniform.get_fractal_kind().is_julia() {
+            self.uniform.c
+        } else {
+            self.uniform.center
+        };
+        let c = [c_reference[0] + dc[0] as f32, c_reference[1] + dc[1] as f32];
+        let mut derivative = derivative_start;
+        let mut trap = TRAP_DISTANCE_MAX;
+        let mut average = Average::default();
+        let reference_point = |n: u32| self.orbit[n as usize].map(|value| value as f64);
+        let z_start = reference_point(0);
+        let mut dz = dz_start;
+        let mut n = 0;
+        let mut z_norm_squared = 0.0;
+        let mut i = 0;
+        while i < iterations_max {
+            let mut z_reference = reference_point(n);
+            let z = [
+                (z_reference[0] + dz[0]) as f32,
+                (z_reference[1] + dz[1]) as f32,
+            ];
+            z_norm_squared = z[0] * z[0] + z[1] * z[1];
+            if averaging && i > 0 {
+                self.add_average_term(&mut average, z, c);
+            }
+            if z_norm_squared > bailout_squared {
+                break;
+            }
+            if i > 0 {
+                trap = trap.min(self.get_trap_distance(z));
+            }
+            if distance {
+                let z = z.map(|part| part as f64);
+                let power = self.uniform.power.map(|part| part as f64);
+                let w = complex_pow(formula_before(self.formula, z), self.power);
+                let d = formula_derivative(self.formula, power, z, w, derivative);
+                derivative = add(d, derivative_c);
+            }
+
+            let dz_rebased = [
+                z_reference[0] - z_start[0] + dz[0],
+                z_reference[1] - z_start[1] + dz[1],
+            ];
+            if n + 1 >= self.uniform.reference_length
+                || dz_rebased[0].hypot(dz_rebased[1]) < dz[0].hypot(dz[1])
+            {
+                dz = dz_rebased;
+                n = 0;
+                z_reference = z_start;
+            }
+
+            let [re, im] = self.perturbation_step(z_reference, dz);
+            dz = [re + dc[0], im + dc[1]];
+            n += 1;
+            i += 1;
+        }
+        let derivative_log2 =
+            0.5 * (derivative[0] * derivative[0] + derivative[1] * derivative[1]).log2();
+        let z_reference = reference_point(n);
+        let z = [
+            (z_reference[0] + dz[0]) as f32,
+            (z_reference[1] + dz[1]) as f32,
+        ];
+        let interior = self.get_interior(z, [0.0, 0.0], 0);
+        self.get_escape(
+            i,
+            z_norm_squared,
+            derivative_log2 as f32,
+            trap,
+            interior,
+            &average,
+        )
+    }
+
+    /// Same as `apply_formula`.
+    fn apply_formula(&self, z: [f32; 2]) -> [f32; 2] {
+        let before = formula_before(self.formula, z);
+        formula_after(self.formula, self.apply_power(before))
+    }
+
+    /// `z^power` as in `apply_formula`.
+    fn apply_power(&self, z: [f32; 2]) -> [f32; 2] {
+        if self.power == 0 {
+            return complex_pow_polar(z, self.uniform.power);
+        }
+        complex_pow(z, self.power)
+    }
+
+    /// Same as `perturbation_step` without `dc`.
+    fn perturbation_step(&self, z_reference: [f64; 2], dz: [f64; 2]) -> [f64; 2] {
+        let n = self.power;
+        let [x, y] = z_reference;
+        let b = formula_before(self.formula, z_reference);
+        let db = match self.formula {
+            Formula::BurningShip => [diffabs(x, dz[0]), diffabs(y, dz[1])],
+            Formula::Tricorn => [dz[0], -dz[1]],
+            Formula::Perpendicular => [diffabs(x, dz[0]), -dz[1]],
+            _ => dz,
+        };
+
+        let mut b_power = [1.0, 0.0];
+        let mut sum = b_power;
+        let mut binomial = 1.0;
+        for k in (1..n).rev() {
+            b_power = complex_mul(b_power, b);
+            binomial = binomial * (k + 1) as f64 / (n - k) as f64;
+            let [re, im] = complex_mul(db, sum);
+            sum = [binomial * b_power[0] + re, binomial * b_power[1] + im];
+        }
+        let d = complex_mul(db, sum);
+
+        let w = complex_pow(b, n);
+        match self.formula {
+            Formula::Celtic => [diffabs(w[0], d[0]), d[1]],
+            Formula::Buffalo => [diffabs(w[0], d[0]), -diffabs(w[1], d[1])],
+            _ => d,
+        }
+    }
+
+    /// Root and iterations as in `NewtonResult`, the root is `None` if the point does not converge.
+    fn newton(&self, z_start: [f32; 2], c: [f32; 2]) -> (Option<usize>, f32) {
+        let uniform = &self.uniform;
+        let iterations_max = uniform.max_iterations as i32;
+        let degree = uniform.degree as usize;
+        let coefficient = |k: usize| [uniform.polynomial[k][0], uniform.polynomial[k][1]];
+        let root = |k: usize| [uniform.polynomial[k][2], uniform.polynomial[k][3]];
+        let norm_squared = |[x, y]: [f32; 2]| x * x + y * y;
+
+        let mut z = z_start;
+        let mut step_norm_squared = 1.0;
+        let mut i = 0;
+        while i < iterations_max {
+            let mut p = coefficient(degree);
+            let mut dp = [0.0, 0.0];
+            let mut ddp_half = [0.0, 0.0];
+            for k in (0..degree).rev() {
+                ddp_half = add(complex_mul(ddp_half, z), dp);
+                dp = add(complex_mul(dp, z), p);
+                p = add(complex_mul(p, z), coefficient(k));
+            }
+            if dp == [0.0, 0.0] {
+                return (None, 0.0);
+            }
+
+            let mut step = complex_div(p, dp);
+            if uniform.newton_method == NEWTON_METHOD_HALLEY {
+                let correction = complex_div(complex_mul(p, ddp_half), dp);
+                step = complex_div(p, [dp[0] - correction[0], dp[1] - correction[1]]);
+            }
+            let [dx, dy] = complex_mul(uniform.relaxation, step);
+            let z_next = [z[0] - dx + c[0], z[1] - dy + c[1]];
+            step_norm_squared = norm_squared([z_next[0] - z[0], z_next[1] - z[1]]);
+            z = z_next;
+            if step_norm_squared < NEWTON_TOLERANCE_SQUARED {
+                break;
+            }
+            i += 1;
+        }
+        if i >= iterations_max {
+            return (None, 0.0);
+        }
+
+        let distance_squared = |k: usize| norm_squared([root(k)[0] - z[0], root(k)[1] - z[1]]);
+        let mut nearest = 0;
+        for k in 1..degree {
+            if distance_squared(k) < distance_squared(nearest) {
+                nearest = k;
+            }
+        }
+
+        if uniform.coloring_mode == COLORING_BANDED {
+            return (Some(nearest), i as f32);
+        }
+        let order: f32 = if uniform.newton_method == NEWTON_METHOD_HALLEY {
+            3.0
+        } else {
+            2.0
+        };
+        let ratio = step_norm_squared.ln() / NEWTON_TOLERANCE_SQUARED.ln();
+        (
+            Some(nearest),
+            (i as f32 - ratio.log2() / order.log2()).max(0.0),
+        )
+    }
+
+    fn get_newton_color(&self, (root, iterations): (Option<usize>, f32)) -> [u8; 3] {
+        let Some(root) = root else {
+            return [0, 0, 0];
+        };
+        let position = ((root as f32 + 0.5) / self.uniform.degree as f32
+            + self.uniform.palette_offset)
+            .rem_euclid(1.0);
+        let shade = (-iterations / NEWTON_SHADING).exp();
+        self.sample_palette(position)
+            .map(|value| (value * shade).round() as u8)
+    }
+
+    fn get_escape_color(&self, escape: Escape) -> [u8; 3] {
+        if self.uniform.coloring_mode == COLORING_TRAP {
+            return self.get_trap_color(escape.trap);
+        }
+        if escape.fraction >= 1.0 {
+            return self.get_interior_color(escape.interior);
+        }
+        if self.is_averaging() {
+            let position = (escape.average + self.uniform.palette_offset).rem_euclid(1.0);
+            return self
+                .sample_palette(position)
+                .map(|value| value.round() as u8);
+        }
+        let shade = escape.distance.clamp(0.0, 1.0);
+        self.get_color(escape.fraction)
+            .map(|value| (value as f32 * shade).round() as u8)
+    }
+
+    fn get_color(&self, fraction: f32) -> [u8; 3] {
+        if fraction >= 1.0 {
+            return [0, 0, 0];
+        }
+
+        let iterations = fraction * self.uniform.max_iterations as f32;
+        let position = (iterations / self.uniform.palette_period + self.uniform.palette_offset)
+            .rem_euclid(1.0);
+        self.sample_palette(position)
+            .map(|value| value.round() as u8)
+    }
+
+    fn get_trap_color(&self, trap: f32) -> [u8; 3] {
+        let octaves = -trap.max(1e-30).log2();
+        let position =
+            (octaves / TRAP_PALETTE_PERIOD + self.uniform.palette_offset).rem_euclid(1.0);
+        self.sample_palette(position)
+            .map(|value| value.round() as u8)
+    }
+
+    fn get_interior_color(&self, [position, shade]: [f32; 2]) -> [u8; 3] {
+        if self.uniform.interior_mode == INTERIOR_BLACK {
+            return [0, 0, 0];
+        }
+        let position = (position + self.uniform.palette_offset).rem_euclid(1.0);
+        self.sample_palette(position)
+            .map(|value| (value * shade).round() as u8)
+    }
+
+    /// Linear filtering with repeat addressing, like the palette sampler, from 0.0 to 255.0.
+    fn sample_palette(&self, position: f32) -> [f32; 3] {
+        let texel = position * PALETTE_WIDTH as f32 - 0.5;
+        let t = texel - texel.floor();
+        let before = self.palette[(texel.floor() as i32).rem_euclid(PALETTE_WIDTH as i32) as usize];
+        let after =
+            self.palette[(texel.floor() as i32 + 1).rem_euclid(PALETTE_WIDTH as i32) as usize];
+        [0, 1, 2].map(|i| before[i] as f32 * (1.0 - t) + after[i] as f32 * t)
+    }
+}
+
+/// `f32` for single precision and `f64` for the rest, so they share the formulas.
+trait Real:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    const ZERO: Self;
+
+    fn abs(self) -> Self;
+}
+
+impl Real for f32 {
+    const ZERO: Self = 0.0;
+
+    fn abs(self) -> Self {
+        f32::abs(self)
+    }
+}
+
+impl Real for f64 {
+    const ZERO: Self = 0.0;
+
+    fn abs(self) -> Self {
+        f64::abs(self)
+    }
+}
+
+/// Same as `formula_before` in `shader.wgsl`.
+fn formula_before<T: Real>(formula: Formula, [x, y]: [T; 2]) -> [T; 2] {
+    match formula {
+        Formula::BurningShip => [x.abs(), y.abs()],
+        Formula::Tricorn => [x, -y],
+        Formula::Perpendicular => [x.abs(), -y],
+        _ => [x, y],
+    }
+}
+
+/// Same as `formula_after` in `shader.wgsl`.
+fn formula_after<T: Real>(formula: Formula, [re, im]: [T; 2]) -> [T; 2] {
+    match formula {
+        Formula::Celtic => [re.abs(), im],
+        Formula::Buffalo => [re.abs(), -im.abs()],
+        _ => [re, im],
+    }
+}
+
+/// Same as `formula_derivative` in `shader.wgsl`, `w` is `formula_before(z)^power`.
+fn formula_derivative<T: Real>(
+    formula: Formula,
+    power: [T; 2],
+    z: [T; 2],
+    w: [T; 2],
+    dz: [T; 2],
+) -> [T; 2] {
+    let before = formula_before(formula, z);
+    let flip = |value: T, sign: T| if sign < T::ZERO { -value } else { value };
+    let d_before = match formula {
+        Formula::BurningShip => [flip(dz[0], z[0]), flip(dz[1], z[1])],
+        Formula::Tricorn => [dz[0], -dz[1]],
+        Formula::Perpendicular => [flip(dz[0], z[0]), -dz[1]],
+        _ => dz,
+    };
+
+    if before[0] == T::ZERO && before[1] == T::ZERO {
+        return [T::ZERO, T::ZERO];
+    }
+    let dw = complex_mul(complex_mul(power, complex_div(w, before)), d_before);
+
+    match formula {
+        Formula::Celtic => [flip(dw[0], w[0]), dw[1]],
+        Formula::Buffalo => [flip(dw[0], w[0]), -flip(dw[1], w[1])],
+        _ => dw,
+    }
+}
+
+fn complex_mul<T: Real>(a: [T; 2], b: [T; 2]) -> [T; 2] {
+    [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]]
+}
+
+/// Same as the WGSL generated by `Expression::to_wgsl`.
+fn evaluate(node: &Node, z: [f32; 2], c: [f32; 2]) -> [f32; 2] {
+    match node {
+        Node::Number([re, im]) => [*re as f32, *im as f32],
+        Node::Z => z,
+        Node::C => c,
+        Node::Negate(node) => evaluate(node, z, c).map(|part| -part),
+        Node::Binary(operator, a, b) => {
+            let a_value = evaluate(a, z, c);
+            match operator {
+                Operator::Add => add(a_value, evaluate(b, z, c)),
+                Operator::Subtract => {
+                    let b_value = evaluate(b, z, c);
+                    [a_value[0] - b_value[0], a_value[1] - b_value[1]]
+                }
+                Operator::Multiply => complex_mul(a_value, evaluate(b, z, c)),
+                Operator::Divide => complex_div(a_value, evaluate(b, z, c)),
+                Operator::Power => match b.get_integer() {
+                    Some(0) => [1.0, 0.0],
+                    Some(1) => a_value,
+                    Some(-1) => complex_div([1.0, 0.0], a_value),
+                    Some(n) if n < 0 => {
+                        complex_div([1.0, 0.0], complex_pow(a_value, n.unsigned_abs()))
+                    }
+                    Some(n) => complex_pow(a_value, n as u32),
+                    None => complex_pow_polar(a_value, evaluate(b, z, c)),
+                },
+            }
+        }
+        Node::Call(function, node) => {
+            let [x, y] = evaluate(node, z, c);
+            match function {
+                Function::Exp => complex_exp([x, y]),
+                Function::Log => [0.5 * (x * x + y * y).ln(), y.atan2(x)],
+                Function::Sqrt => {
+                    let r = (x * x + y * y).sqrt();
+                    let im = (0.5 * (r - x)).max(0.0).sqrt();
+                    [
+                        (0.5 * (r + x)).max(0.0).sqrt(),
+                        if y < 0.0 { -im } else { im },
+                    ]
+                }
+                Function::Sin => complex_sin([x, y]),
+                Function::Cos => complex_cos([x, y]),
+                Function::Tan => complex_div(complex_sin([x, y]), complex_cos([x, y])),
+                Function::Sinh => complex_sinh([x, y]),
+                Function::Cosh => complex_cosh([x, y]),
+                Function::Tanh => complex_div(complex_sinh([x, y]), complex_cosh([x, y])),
+                Function::Conj => [x, -y],
+                Function::Abs => [(x * x + y * y).sqrt(), 0.0],
+                Function::Re => [x, 0.0],
+                Function::Im => [y, 0.0],
+            }
+        }
+    }
+}
+
+fn complex_exp([x, y]: [f32; 2]) -> [f32; 2] {
+    let scale = x.exp();
+    [scale * y.cos(), scale * y.sin()]
+}
+
+fn complex_sin([x, y]: [f32; 2]) -> [f32; 2] {
+    [x.sin() * y.cosh(), x.cos() * y.sinh()]
+}
+
+fn complex_cos([x, y]: [f32; 2]) -> [f32; 2] {
+    [x.cos() * y.cosh(), -x.sin() * y.sinh()]
+}
+
+fn complex_sinh([x, y]: [f32; 2]) -> [f32; 2] {
+    [x.sinh() * y.cos(), x.cosh() * y.sin()]
+}
+
+fn complex_cosh([x, y]: [f32; 2]) -> [f32; 2] {
+    [x.cosh() * y.cos(), x.sinh() * y.sin()]
+}
+
+/// Same as `complex_pow` in `shader.wgsl`, `z^n` for `n >= 2`.
+fn add<T: Real>(a: [T; 2], b: [T; 2]) -> [T; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+fn complex_div<T: Real>(a: [T; 2], b: [T; 2]) -> [T; 2] {
+    let norm_squared = b[0] * b[0] + b[1] * b[1];
+    [
+        (a[0] * b[0] + a[1] * b[1]) / norm_squared,
+        (a[1] * b[0] - a[0] * b[1]) / norm_squared,
+    ]
+}
+
+/// Same as `complex_pow` in `shader.wgsl`, `z^n` for `n >= 2`.
+fn complex_pow<T: Real>(z: [T; 2], n: u32) -> [T; 2] {
+    let xy = z[0] * z[1];
+    let mut w = [z[0] * z[0] - z[1] * z[1], xy + xy];
+    for _ in 2..n {
+        w = complex_mul(w, z);
+    }
+    w
+}
+
+/// Same as `complex_pow_polar` in `shader.wgsl`.
+fn complex_pow_polar(z: [f32; 2], d: [f32; 2]) -> [f32; 2] {
+    if z == [0.0, 0.0] {
+        return z;
+    }
+    let log_z = [0.5 * (z[0] * z[0] + z[1] * z[1]).ln(), z[1].atan2(z[0])];
+    let [re, im] = complex_mul(d, log_z);
+    [re.exp() * im.cos(), re.exp() * im.sin()]
+}
+
+/// `|c + d| - |c|` without cancellation when `d` is much smaller than `c`.
+fn diffabs(c: f64, d: f64) -> f64 {
+    if c >= 0.0 {
+        if c + d >= 0.0 {
+            d
+        } else {
+            -(2.0 * c + d)
+        }
+    } else if c + d > 0.0 {
+        2.0 * c + d
+    } else {
+        -d
+    }
+}
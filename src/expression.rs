@@ -0,0 +1,646 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::uniform::POWER_INTEGER_MAX;
+
+/// Deepest nesting of operations in a formula, keeps the parser and the generated shader
+/// from going arbitrarily deep.
+const DEPTH_MAX: usize = 64;
+
+/// Iteration formula typed in by the user, `z -> f(z, c)`, such as `z = z^3 + c*sin(z)`.
+///
+/// Formulas are made of the complex numbers `z` and `c`, decimal numbers, `i` and numbers
+/// followed by `i` for imaginary ones, `+ - * / ^` with the usual precedence (`^` binds
+/// tightest and groups to the right), parentheses and the functions in `Function`.
+/// The leading `z =` is optional. Whole powers up to `POWER_INTEGER_MAX` are iterated by
+/// multiplication, others go through the polar form like `Uniform::power`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    /// As typed, written back by `Display`.
+    source: String,
+    root: Node,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// Real and imaginary parts.
+    Number([f64; 2]),
+    Z,
+    C,
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Box<Node>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+/// Functions of one complex number, `abs`, `re` and `im` give real numbers.
+/// Multivalued ones take the principal branch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Sqrt,
+    Exp,
+    Log,
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Conj,
+    Abs,
+    Re,
+    Im,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExpressionError {
+    Empty,
+    /// Character that starts no token, and its column.
+    UnexpectedCharacter(usize, char),
+    /// Token that does not fit where it is, and its column.
+    UnexpectedToken(usize, String),
+    UnexpectedEnd,
+    /// Name that is neither a variable nor a function, and its column.
+    UnknownName(usize, String),
+    /// Number that does not fit in `f32`, and its column.
+    OutOfRange(usize, String),
+    TooDeep,
+    /// The shader generated from the formula does not validate.
+    Shader(String),
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpressionError::Empty => write!(f, "the formula is empty"),
+            ExpressionError::UnexpectedCharacter(column, character) => {
+                write!(f, "unexpected '{}' at column {}", character, column)
+            }
+            ExpressionError::UnexpectedToken(column, token) => {
+                write!(f, "unexpected '{}' at column {}", token, column)
+            }
+            ExpressionError::UnexpectedEnd => write!(f, "the formula ends too early"),
+            ExpressionError::UnknownName(column, name) => {
+                let functions: Vec<_> = Function::ALL.iter().map(|f| f.get_name()).collect();
+                write!(
+                    f,
+                    "unknown name '{}' at column {}, the variables are z and c, \
+                     the imaginary unit i and the functions {}",
+                    name,
+                    column,
+                    functions.join(", ")
+                )
+            }
+            ExpressionError::OutOfRange(column, number) => {
+                write!(f, "number {} at column {} is out of range", number, column)
+            }
+            ExpressionError::TooDeep => write!(
+                f,
+                "the formula nests more than {} operations deep",
+                DEPTH_MAX
+            ),
+            ExpressionError::Shader(message) => {
+                write!(f, "the formula does not compile: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+impl Default for Expression {
+    /// `z^2 + c`, the Mandelbrot formula, which the shader computes as written.
+    fn default() -> Self {
+        "z^2 + c".parse().expect("default formula is valid")
+    }
+}
+
+impl Expression {
+    pub fn get_root(&self) -> &Node {
+        &self.root
+    }
+
+    /// WGSL expression of the formula in terms of `z` and `c`, both `vec2<f32>`, calling
+    /// the complex functions of `shader.wgsl`.
+    pub fn to_wgsl(&self) -> String {
+        self.root.to_wgsl()
+    }
+
+    /// `d` such that `|f(z, c)|` grows like `|z|^d` for large `z`, which smooth colouring
+    /// takes as the power. `None` if it grows faster than any power, as `exp` does, or
+    /// the exponent of a power is not a number.
+    pub fn get_degree(&self) -> Option<f64> {
+        self.root.get_degree()
+    }
+}
+
+impl Node {
+    /// The value of a number or of a negated one.
+    pub fn get_constant(&self) -> Option<[f64; 2]> {
+        match self {
+            Node::Number(value) => Some(*value),
+            Node::Negate(node) => node.get_constant().map(|[re, im]| [-re, -im]),
+            _ => None,
+        }
+    }
+
+    /// The value of a constant whole number up to `POWER_INTEGER_MAX` in size, as exponent
+    /// it is iterated by multiplication.
+    pub fn get_integer(&self) -> Option<i32> {
+        let [re, im] = self.get_constant()?;
+        let n = re as i32;
+        (im == 0.0 && n as f64 == re && n.unsigned_abs() <= POWER_INTEGER_MAX).then_some(n)
+    }
+
+    fn to_wgsl(&self) -> String {
+        match self {
+            Node::Number([re, im]) => format!("vec2<f32>({:?}, {:?})", *re as f32, *im as f32),
+            Node::Z => "z".to_string(),
+            Node::C => "c".to_string(),
+            Node::Negate(node) => format!("(-{})", node.to_wgsl()),
+            Node::Binary(operator, a, b) => {
+                let (a_wgsl, b_wgsl) = (a.to_wgsl(), b.to_wgsl());
+                match operator {
+                    Operator::Add => format!("({} + {})", a_wgsl, b_wgsl),
+                    Operator::Subtract => format!("({} - {})", a_wgsl, b_wgsl),
+                    Operator::Multiply => format!("complex_mul({}, {})", a_wgsl, b_wgsl),
+                    Operator::Divide => format!("complex_div({}, {})", a_wgsl, b_wgsl),
+                    Operator::Power => match b.get_integer() {
+                        Some(0) => "vec2<f32>(1.0, 0.0)".to_string(),
+                        Some(1) => a_wgsl,
+                        Some(-1) => format!("complex_div(vec2<f32>(1.0, 0.0), {})", a_wgsl),
+                        Some(n) if n < 0 => format!(
+                            "complex_div(vec2<f32>(1.0, 0.0), complex_pow({}, {}))",
+                            a_wgsl, -n
+                        ),
+                        Some(n) => format!("complex_pow({}, {})", a_wgsl, n),
+                        None => format!("complex_pow_polar({}, {})", a_wgsl, b_wgsl),
+                    },
+                }
+            }
+            Node::Call(function, node) => {
+                format!("complex_{}({})", function.get_name(), node.to_wgsl())
+            }
+        }
+    }
+
+    fn get_degree(&self) -> Option<f64> {
+        match self {
+            Node::Number(_) | Node::C => Some(0.0),
+            Node::Z => Some(1.0),
+            Node::Negate(node) => node.get_degree(),
+            Node::Binary(operator, a, b) => {
+                let a_degree = a.get_degree()?;
+                match operator {
+                    Operator::Add | Operator::Subtract => Some(a_degree.max(b.get_degree()?)),
+                    Operator::Multiply => Some(a_degree + b.get_degree()?),
+                    Operator::Divide => Some(a_degree - b.get_degree()?),
+                    Operator::Power => b.get_constant().map(|[re, _]| a_degree * re),
+                }
+            }
+            Node::Call(function, node) => {
+                let degree = node.get_degree()?;
+                match function {
+                    Function::Conj | Function::Abs | Function::Re | Function::Im => Some(degree),
+                    Function::Sqrt => Some(degree / 2.0),
+                    Function::Log => Some(0.0),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    fn get_depth(&self) -> usize {
+        match self {
+            Node::Number(_) | Node::Z | Node::C => 1,
+            Node::Negate(node) | Node::Call(_, node) => 1 + node.get_depth(),
+            Node::Binary(_, a, b) => 1 + a.get_depth().max(b.get_depth()),
+        }
+    }
+}
+
+impl Function {
+    pub const ALL: [Function; 13] = [
+        Function::Sqrt,
+        Function::Exp,
+        Function::Log,
+        Function::Sin,
+        Function::Cos,
+        Function::Tan,
+        Function::Sinh,
+        Function::Cosh,
+        Function::Tanh,
+        Function::Conj,
+        Function::Abs,
+        Function::Re,
+        Function::Im,
+    ];
+
+    /// Name in formulas, and in `shader.wgsl` after `complex_`.
+    pub fn get_name(self) -> &'static str {
+        match self {
+            Function::Sqrt => "sqrt",
+            Function::Exp => "exp",
+            Function::Log => "log",
+            Function::Sin => "sin",
+            Function::Cos => "cos",
+            Function::Tan => "tan",
+            Function::Sinh => "sinh",
+            Function::Cosh => "cosh",
+            Function::Tanh => "tanh",
+            Function::Conj => "conj",
+            Function::Abs => "abs",
+            Function::Re => "re",
+            Function::Im => "im",
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut tokens = tokenize(source)?;
+        // the formula may start with z =
+        if let [(_, Token::Name(name)), (_, Token::Symbol('=')), ..] = tokens.as_slice() {
+            if name == "z" {
+                tokens.drain(..2);
+            }
+        }
+        if tokens.is_empty() {
+            return Err(ExpressionError::Empty);
+        }
+
+        let mut parser = Parser {
+            tokens,
+            index: 0,
+            depth: 0,
+        };
+        let root = parser.parse_sum()?;
+        if let Some((column, token)) = parser.tokens.get(parser.index) {
+            return Err(ExpressionError::UnexpectedToken(*column, token.to_string()));
+        }
+        if root.get_depth() > DEPTH_MAX {
+            return Err(ExpressionError::TooDeep);
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// Value and whether it was followed by `i`.
+    Number(f64, bool),
+    Name(String),
+    Symbol(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value, false) => write!(f, "{}", value),
+            Token::Number(value, true) => write!(f, "{}i", value),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+/// Tokens with the column they start at, counting from 1.
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let characters: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < characters.len() {
+        let (column, character) = (index + 1, characters[index]);
+        if character.is_whitespace() {
+            index += 1;
+        } else if character.is_ascii_digit() || character == '.' {
+            let start = index;
+            while index < characters.len()
+                && (characters[index].is_ascii_digit() || characters[index] == '.')
+            {
+                index += 1;
+            }
+            // exponent, only if digits follow so that 2e is not read as a number
+            let exponent_digits = |offset: usize| {
+                characters
+                    .get(index + offset)
+                    .is_some_and(|c| c.is_ascii_digit())
+            };
+            if matches!(characters.get(index), Some('e' | 'E'))
+                && (exponent_digits(1)
+                    || matches!(characters.get(index + 1), Some('+' | '-')) && exponent_digits(2))
+            {
+                index += 2;
+                while index < characters.len() && characters[index].is_ascii_digit() {
+                    index += 1;
+                }
+            }
+            let text: String = characters[start..index].iter().collect();
+            let value: f64 = text
+                .parse()
+                .map_err(|_| ExpressionError::UnexpectedToken(column, text.clone()))?;
+            if !(value as f32).is_finite() {
+                return Err(ExpressionError::OutOfRange(column, text));
+            }
+            let imaginary = characters.get(index) == Some(&'i')
+                && !characters
+                    .get(index + 1)
+                    .is_some_and(|c| c.is_alphanumeric() || *c == '_');
+            if imaginary {
+                index += 1;
+            }
+            tokens.push((column, Token::Number(value, imaginary)));
+        } else if character.is_alphabetic() {
+            let start = index;
+            while index < characters.len()
+                && (characters[index].is_alphanumeric() || characters[index] == '_')
+            {
+                index += 1;
+            }
+            tokens.push((
+                column,
+                Token::Name(characters[start..index].iter().collect()),
+            ));
+        } else if "+-*/^()=".contains(character) {
+            tokens.push((column, Token::Symbol(character)));
+            index += 1;
+        } else {
+            return Err(ExpressionError::UnexpectedCharacter(column, character));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent, one function per precedence level.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    /// Depth of the tree being built, counting every operand of a sum or product as a level
+    /// as each nests the terms before it, so that deep trees are rejected before they are
+    /// built and not only by the recursion of `parse_unary`.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek_symbol(&self) -> Option<char> {
+        match self.tokens.get(self.index) {
+            Some((_, Token::Symbol(symbol))) => Some(*symbol),
+            _ => None,
+        }
+    }
+
+    fn expect_symbol(&mut self, expected: char) -> Result<(), ExpressionError> {
+        match self.tokens.get(self.index) {
+            Some((_, Token::Symbol(symbol))) if *symbol == expected => {
+                self.index += 1;
+                Ok(())
+            }
+            Some((column, token)) => {
+                Err(ExpressionError::UnexpectedToken(*column, token.to_string()))
+            }
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+
+    /// Go one level deeper, or fail if that is deeper than `DEPTH_MAX`.
+    fn descend(&mut self) -> Result<(), ExpressionError> {
+        self.depth += 1;
+        if self.depth > DEPTH_MAX {
+            return Err(ExpressionError::TooDeep);
+        }
+        Ok(())
+    }
+
+    fn parse_sum(&mut self) -> Result<Node, ExpressionError> {
+        let depth = self.depth;
+        let mut node = self.parse_product()?;
+        while let Some(symbol @ ('+' | '-')) = self.peek_symbol() {
+            self.descend()?;
+            self.index += 1;
+            let operator = if symbol == '+' {
+                Operator::Add
+            } else {
+                Operator::Subtract
+            };
+            node = Node::Binary(operator, Box::new(node), Box::new(self.parse_product()?));
+        }
+        self.depth = depth;
+        Ok(node)
+    }
+
+    fn parse_product(&mut self) -> Result<Node, ExpressionError> {
+        let depth = self.depth;
+        let mut node = self.parse_unary()?;
+        while let Some(symbol @ ('*' | '/')) = self.peek_symbol() {
+            self.descend()?;
+            self.index += 1;
+            let operator = if symbol == '*' {
+                Operator::Multiply
+            } else {
+                Operator::Divide
+            };
+            node = Node::Binary(operator, Box::new(node), Box::new(self.parse_unary()?));
+        }
+        self.depth = depth;
+        Ok(node)
+    }
+
+    /// Negation binds looser than powers, `-z^2` is `-(z^2)`.
+    fn parse_unary(&mut self) -> Result<Node, ExpressionError> {
+        self.descend()?;
+        let node = if self.peek_symbol() == Some('-') {
+            self.index += 1;
+            Node::Negate(Box::new(self.parse_unary()?))
+        } else {
+            let base = self.parse_primary()?;
+            if self.peek_symbol() == Some('^') {
+                self.index += 1;
+                let exponent = self.parse_unary()?;
+                Node::Binary(Operator::Power, Box::new(base), Box::new(exponent))
+            } else {
+                base
+            }
+        };
+        self.depth -= 1;
+        Ok(node)
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, ExpressionError> {
+        let Some((column, token)) = self.tokens.get(self.index).cloned() else {
+            return Err(ExpressionError::UnexpectedEnd);
+        };
+        self.index += 1;
+        match token {
+            Token::Number(value, false) => Ok(Node::Number([value, 0.0])),
+            Token::Number(value, true) => Ok(Node::Number([0.0, value])),
+            Token::Name(name) => match name.as_str() {
+                "z" => Ok(Node::Z),
+                "c" => Ok(Node::C),
+                "i" => Ok(Node::Number([0.0, 1.0])),
+                _ => {
+                    let function = Function::ALL
+                        .into_iter()
+                        .find(|function| function.get_name() == name)
+                        .ok_or(ExpressionError::UnknownName(column, name))?;
+                    self.expect_symbol('(')?;
+                    let argument = self.parse_sum()?;
+                    self.expect_symbol(')')?;
+                    Ok(Node::Call(function, Box::new(argument)))
+                }
+            },
+            Token::Symbol('(') => {
+                let node = self.parse_sum()?;
+                self.expect_symbol(')')?;
+                Ok(node)
+            }
+            Token::Symbol(_) => Err(ExpressionError::UnexpectedToken(column, token.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Node {
+        source.parse::<Expression>().unwrap().root
+    }
+
+    fn binary(operator: Operator, a: Node, b: Node) -> Node {
+        Node::Binary(operator, Box::new(a), Box::new(b))
+    }
+
+    fn number(value: f64) -> Node {
+        Node::Number([value, 0.0])
+    }
+
+    #[test]
+    fn follows_precedence() {
+        let square = binary(Operator::Power, Node::Z, number(2.0));
+        assert_eq!(
+            parse("z = z^2 + c"),
+            binary(Operator::Add, square.clone(), Node::C)
+        );
+        assert_eq!(parse("-z^2"), Node::Negate(Box::new(square)));
+        assert_eq!(
+            parse("z + c * 2"),
+            binary(
+                Operator::Add,
+                Node::Z,
+                binary(Operator::Multiply, Node::C, number(2.0))
+            )
+        );
+        assert_eq!(
+            parse("(z + c) * 2"),
+            binary(
+                Operator::Multiply,
+                binary(Operator::Add, Node::Z, Node::C),
+                number(2.0)
+            )
+        );
+    }
+
+    #[test]
+    fn associates() {
+        // sums and products from the left, powers from the right
+        assert_eq!(
+            parse("z - c - 1"),
+            binary(
+                Operator::Subtract,
+                binary(Operator::Subtract, Node::Z, Node::C),
+                number(1.0)
+            )
+        );
+        assert_eq!(
+            parse("z / c / 2"),
+            binary(
+                Operator::Divide,
+                binary(Operator::Divide, Node::Z, Node::C),
+                number(2.0)
+            )
+        );
+        assert_eq!(
+            parse("z^2^3"),
+            binary(
+                Operator::Power,
+                Node::Z,
+                binary(Operator::Power, number(2.0), number(3.0))
+            )
+        );
+    }
+
+    #[test]
+    fn reads_numbers_and_calls() {
+        assert_eq!(parse("2.5i"), Node::Number([0.0, 2.5]));
+        assert_eq!(parse("1e3"), number(1000.0));
+        assert_eq!(parse("i"), Node::Number([0.0, 1.0]));
+        assert_eq!(
+            parse("sin(z) + abs(c)"),
+            binary(
+                Operator::Add,
+                Node::Call(Function::Sin, Box::new(Node::Z)),
+                Node::Call(Function::Abs, Box::new(Node::C))
+            )
+        );
+        let expression: Expression = "  z^3 + c ".parse().unwrap();
+        assert_eq!(expression.to_string(), "z^3 + c");
+        assert_eq!(expression.get_degree(), Some(3.0));
+    }
+
+    #[test]
+    fn reports_errors() {
+        let error = |source: &str| source.parse::<Expression>().unwrap_err();
+        assert_eq!(error(""), ExpressionError::Empty);
+        assert_eq!(error("z ="), ExpressionError::Empty);
+        assert_eq!(error("z + $"), ExpressionError::UnexpectedCharacter(5, '$'));
+        assert_eq!(
+            error("z c"),
+            ExpressionError::UnexpectedToken(3, "c".to_string())
+        );
+        assert_eq!(error("z +"), ExpressionError::UnexpectedEnd);
+        assert_eq!(error("(z + c"), ExpressionError::UnexpectedEnd);
+        assert_eq!(
+            error("foo(z)"),
+            ExpressionError::UnknownName(1, "foo".to_string())
+        );
+        assert_eq!(
+            error("z + 1e99"),
+            ExpressionError::OutOfRange(5, "1e99".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_deep_formulas() {
+        let nested = format!("{}z{}", "(".repeat(DEPTH_MAX), ")".repeat(DEPTH_MAX));
+        assert_eq!(nested.parse::<Expression>(), Err(ExpressionError::TooDeep));
+        let negated = format!("{}z", "-".repeat(DEPTH_MAX + 1));
+        assert_eq!(negated.parse::<Expression>(), Err(ExpressionError::TooDeep));
+        // long sums would overflow the stack when dropped if they were built
+        let sum = format!("z{}", "+z".repeat(100_000));
+        assert_eq!(sum.parse::<Expression>(), Err(ExpressionError::TooDeep));
+        let product = format!("z{}", "*z".repeat(100_000));
+        assert_eq!(product.parse::<Expression>(), Err(ExpressionError::TooDeep));
+        assert!(format!("z{}", "+z".repeat(DEPTH_MAX / 2))
+            .parse::<Expression>()
+            .is_ok());
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+/// Iterated map `z -> f(z) + c`, with `z = x + iy`. Numbered as in `shader.wgsl`.
+///
+/// The maps are given for `Uniform::power` 2. Every formula is `f(z) = after(before(z)^d)`,
+/// where `before` and `after` take absolute values or conjugates of the parts, so other
+/// powers apply the same changes around `z^d`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Formula {
+    /// `f(z) = z^2`
+    Mandelbrot,
+    /// `f(z) = (|x| + i|y|)^2`
+    BurningShip,
+    /// `f(z) = conj(z)^2`, also known as the Mandelbar.
+    Tricorn,
+    /// `f(z) = |x^2 - y^2| + 2ixy`
+    Celtic,
+    /// `f(z) = x^2 - y^2 - 2i|x|y`
+    Perpendicular,
+    /// `f(z) = |x^2 - y^2| - 2i|xy|`
+    Buffalo,
+    /// `f(z) = z - a p(z) / p'(z)`, the Newton step for the roots of the polynomial `p`
+    /// with `Uniform::relaxation` `a`, or Halley's step. Does not escape, points are
+    /// coloured by the root they converge to.
+    Newton,
+    /// `z -> f(z, c)` for an `Expression` typed in by the user, compiled into the shader.
+    Custom,
+}
+
+impl Formula {
+    pub const ALL: [Formula; 8] = [
+        Formula::Mandelbrot,
+        Formula::BurningShip,
+        Formula::Tricorn,
+        Formula::Celtic,
+        Formula::Perpendicular,
+        Formula::Buffalo,
+        Formula::Newton,
+        Formula::Custom,
+    ];
+
+    /// Center of the first view of the parameter plane, where the whole set fits.
+    pub fn get_center(self) -> [f64; 2] {
+        match self {
+            Formula::Mandelbrot => [-0.6, 0.0],
+            Formula::BurningShip => [-0.4, -0.5],
+            Formula::Tricorn => [-0.3, 0.0],
+            Formula::Celtic => [-0.4, 0.0],
+            Formula::Perpendicular => [-0.6, 0.0],
+            Formula::Buffalo => [-0.4, 0.5],
+            Formula::Newton | Formula::Custom => [0.0, 0.0],
+        }
+    }
+}
+
+/// Value of `Uniform::fractal_kind`, what the shader draws.
+///
+/// Every formula comes in two forms: its Julia sets, with `c` fixed and the starting `z`
+/// taken from the view, and its parameter plane (the Mandelbrot set for `z^2 + c`), with
+/// `z` starting at `c` of the uniform and `c` taken from the view. Julia forms have even
+/// values, `fractal_kind / 2` is the formula.
+///
+/// With `c = 0` the Julia form of the Newton formula is plain Newton's method, other
+/// values of `c` give the Julia sets of the Nova fractal, whose parameter plane is `Nova`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum FractalKind {
+    Julia = 0,
+    Mandelbrot = 1,
+    BurningShipJulia = 2,
+    BurningShip = 3,
+    TricornJulia = 4,
+    Tricorn = 5,
+    CelticJulia = 6,
+    Celtic = 7,
+    PerpendicularJulia = 8,
+    Perpendicular = 9,
+    BuffaloJulia = 10,
+    Buffalo = 11,
+    Newton = 12,
+    Nova = 13,
+    CustomJulia = 14,
+    Custom = 15,
+}
+
+impl FractalKind {
+    pub const ALL: [FractalKind; 16] = [
+        FractalKind::Julia,
+        FractalKind::Mandelbrot,
+        FractalKind::BurningShipJulia,
+        FractalKind::BurningShip,
+        FractalKind::TricornJulia,
+        FractalKind::Tricorn,
+        FractalKind::CelticJulia,
+        FractalKind::Celtic,
+        FractalKind::PerpendicularJulia,
+        FractalKind::Perpendicular,
+        FractalKind::BuffaloJulia,
+        FractalKind::Buffalo,
+        FractalKind::Newton,
+        FractalKind::Nova,
+        FractalKind::CustomJulia,
+        FractalKind::Custom,
+    ];
+
+    pub fn new(formula: Formula, julia: bool) -> Self {
+        let index = Formula::ALL.iter().position(|f| *f == formula).unwrap();
+        Self::ALL[index * 2 + !julia as usize]
+    }
+
+    /// `None` for values not in the enum.
+    pub fn from_i32(value: i32) -> Option<Self> {
+        usize::try_from(value)
+            .ok()
+            .and_then(|index| Self::ALL.get(index))
+            .copied()
+    }
+
+    pub fn get_formula(self) -> Formula {
+        Formula::ALL[self as usize / 2]
+    }
+
+    pub fn is_julia(self) -> bool {
+        self as i32 % 2 == 0
+    }
+}
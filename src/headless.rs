@@ -0,0 +1,319 @@
+use std::fmt;
+use std::iter;
+use std::path::Path;
+
+use wgpu::Gles3MinorVersion;
+
+use crate::expression::{Expression, ExpressionError};
+use crate::palette::Palette;
+use crate::refinement::{Blit, Refinement};
+use crate::renderer::{request_device, Renderer};
+use crate::sampling::Sampling;
+use crate::scene::{Scene, SceneError};
+use crate::uniform::Uniform;
+use crate::viewport::Viewport;
+
+/// Format of the offscreen texture, the palette colours are already sRGB encoded.
+const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+#[derive(Debug)]
+pub enum HeadlessError {
+    NoAdapter,
+    /// Requested size is zero or larger than the adapter supports.
+    InvalidSize {
+        width: u32,
+        height: u32,
+        max: u32,
+    },
+    BufferMap(wgpu::BufferAsyncError),
+    Save(SceneError),
+    Formula(ExpressionError),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeadlessError::NoAdapter => write!(f, "no graphics adapter available"),
+            HeadlessError::InvalidSize { width, height, max } => write!(
+                f,
+                "cannot render {}x{}, sizes must be between 1 and {}",
+                width, height, max
+            ),
+            HeadlessError::BufferMap(error) => write!(f, "reading back the image: {}", error),
+            HeadlessError::Save(error) => write!(f, "{}", error),
+            HeadlessError::Formula(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for HeadlessError {}
+
+impl From<SceneError> for HeadlessError {
+    fn from(error: SceneError) -> Self {
+        HeadlessError::Save(error)
+    }
+}
+
+impl From<ExpressionError> for HeadlessError {
+    fn from(error: ExpressionError) -> Self {
+        HeadlessError::Formula(error)
+    }
+}
+
+/// Renders images with the same pipeline as the window, but without one.
+pub struct HeadlessRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    renderer: Renderer,
+    /// Averages the samples of each image.
+    blit: Blit,
+    sampling: Sampling,
+}
+
+impl HeadlessRenderer {
+    pub async fn new(palette: &Palette) -> Result<Self, HeadlessError> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default(),
+            flags: wgpu::InstanceFlags::default(),
+            gles_minor_version: Gles3MinorVersion::default(),
+        });
+        let (adapter, device, queue) = request_device(&instance, None)
+            .await
+            .ok_or(HeadlessError::NoAdapter)?;
+        let renderer = Renderer::new(&device, &queue, TEXTURE_FORMAT, palette);
+        let blit = Blit::new(&device, &adapter, TEXTURE_FORMAT);
+        Ok(Self {
+            device,
+            queue,
+            renderer,
+            blit,
+            sampling: Sampling::default(),
+        })
+    }
+
+    pub fn set_palette(&self, palette: &Palette) {
+        self.renderer.set_palette(&self.queue, palette);
+    }
+
+    /// Take the samples of `sampling` in every pixel of the images rendered from now on.
+    pub fn set_sampling(&mut self, sampling: Sampling) {
+        self.sampling = sampling;
+    }
+
+    /// Compile `formula` into the pipeline for the custom fractal kinds.
+    pub fn set_formula(&mut self, formula: &Expression) -> Result<(), HeadlessError> {
+        Ok(self.renderer.set_formula(&self.device, formula)?)
+    }
+
+    /// Render `viewport` into a `width` by `height` image, keeping its width in the complex plane.
+    /// `uniform` supplies everything else, the fields describing the view and the sample
+    /// are overwritten.
+    pub fn render(
+        &mut self,
+        viewport: &Viewport,
+        uniform: &Uniform,
+        width: u32,
+        height: u32,
+    ) -> Result<image::RgbaImage, HeadlessError> {
+        let max = self.device.limits().max_texture_dimension_2d;
+        if width == 0 || height == 0 || width > max || height > max {
+            return Err(HeadlessError::InvalidSize { width, height, max });
+        }
+
+        let mut viewport = viewport.clone();
+        viewport.set_aspect_ratio(width as f64 / height as f64);
+        let mut uniform = *uniform;
+        viewport.write_uniform(&mut uniform);
+        // one submission per sample, as each takes its own uniform
+        let samples = self.sampling.get_samples();
+        for index in 0..samples {
+            uniform.sample_offset = self.sampling.get_offset(index);
+            self.renderer
+                .prepare(&self.device, &self.queue, &viewport, &mut uniform);
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Headless Sample Encoder"),
+                });
+            let target = self.blit.get_target(&self.device, [width, height]);
+            self.renderer
+                .render(&self.device, &mut encoder, target, width, height);
+            let weight = Refinement::Sample(index).get_weight();
+            self.blit.accumulate(&self.device, &mut encoder, weight);
+            self.queue.submit(iter::once(encoder.finish()));
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // rows of a buffer copy must be aligned
+        let bytes_per_row_unpadded = width * 4;
+        let bytes_per_row = bytes_per_row_unpadded.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Headless Buffer"),
+            size: (bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Headless Encoder"),
+            });
+        self.blit.draw_accumulation(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .expect("map_async callback dropped")
+            .map_err(HeadlessError::BufferMap)?;
+
+        let data = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((bytes_per_row_unpadded * height) as usize);
+        for row in data.chunks(bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..bytes_per_row_unpadded as usize]);
+        }
+        drop(data);
+        buffer.unmap();
+
+        Ok(image::RgbaImage::from_raw(width, height, pixels).expect("image size matches"))
+    }
+
+    /// Render `scene` as in `render` and write the result as a PNG file carrying its parameters.
+    pub fn render_png(
+        &mut self,
+        path: &Path,
+        scene: &Scene,
+        width: u32,
+        height: u32,
+    ) -> Result<(), HeadlessError> {
+        self.set_palette(&scene.palette);
+        self.set_formula(&scene.formula)?;
+        let image = self.render(&scene.viewport, &scene.get_uniform(), width, height)?;
+        scene.save_png(path, &image)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu;
+    use crate::fractal::FractalKind;
+    use crate::sampling::SamplePattern;
+    use crate::uniform::{COLORING_DISTANCE, COLORING_SMOOTH};
+
+    /// Mean difference of the colour channels, from 0 to 255.
+    fn get_mean_difference(a: &image::RgbaImage, b: &image::RgbaImage) -> f64 {
+        let sum: u64 = a
+            .as_raw()
+            .iter()
+            .zip(b.as_raw())
+            .map(|(a, b)| a.abs_diff(*b) as u64)
+            .sum();
+        sum as f64 / a.as_raw().len() as f64
+    }
+
+    /// The CPU renderer follows the shader, apart from rounding in the pixels along edges.
+    #[test]
+    fn matches_cpu_renderer() {
+        let mut scene = Scene::default();
+        let mut renderer = match pollster::block_on(HeadlessRenderer::new(&scene.palette)) {
+            Ok(renderer) => renderer,
+            Err(HeadlessError::NoAdapter) => {
+                eprintln!("skipped, no graphics adapter");
+                return;
+            }
+            Err(error) => panic!("{}", error),
+        };
+        let (width, height) = (96, 64);
+        let samplings = [
+            Sampling {
+                pattern: SamplePattern::Grid,
+                count: 1,
+            },
+            Sampling {
+                pattern: SamplePattern::Jittered,
+                count: 2,
+            },
+        ];
+        // enough iterations near the set for the derivative of distance colouring to
+        // grow beyond f32
+        scene.max_iterations_auto = false;
+        scene.uniform.max_iterations = 2000;
+        let views = [
+            (FractalKind::Mandelbrot, COLORING_SMOOTH),
+            (FractalKind::Julia, COLORING_SMOOTH),
+            (FractalKind::Mandelbrot, COLORING_DISTANCE),
+        ];
+        for (fractal_kind, coloring_mode) in views {
+            scene.uniform.set_fractal_kind(fractal_kind);
+            scene.uniform.coloring_mode = coloring_mode;
+            let uniform = scene.get_uniform();
+            for sampling in samplings {
+                renderer.set_sampling(sampling);
+                let gpu = renderer
+                    .render(&scene.viewport, &uniform, width, height)
+                    .unwrap();
+                let cpu = cpu::render_samples(
+                    &scene.viewport,
+                    &uniform,
+                    &scene.formula,
+                    &scene.palette,
+                    width,
+                    height,
+                    &sampling,
+                );
+                let difference = get_mean_difference(&gpu, &cpu);
+                assert!(
+                    difference < 1.0,
+                    "{:?} coloured by {} with {:?} differs by {} on average",
+                    fractal_kind,
+                    coloring_mode,
+                    sampling,
+                    difference
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::cache::{create_compute_pipeline, WORKGROUP_SIZE};
+
+/// Must match `HISTOGRAM_BINS` in `histogram.wgsl`.
+pub const HISTOGRAM_BINS: u32 = 1024;
+
+/// Shader source of the histogram passes, appended to the fractal shader along with the cache.
+pub const HISTOGRAM_SOURCE: &str = include_str!("shaders/histogram.wgsl");
+
+/// Compute passes counting the iterations of `IterationCache` for `COLORING_HISTOGRAM`,
+/// bound after the uniform, orbit and palette groups.
+pub struct Histogram {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    range_pipeline: wgpu::ComputePipeline,
+    bins_pipeline: wgpu::ComputePipeline,
+    cumulate_pipeline: wgpu::ComputePipeline,
+    /// The `Histogram` struct of `histogram.wgsl`.
+    pub buffer: wgpu::Buffer,
+}
+
+impl Histogram {
+    /// `bind_group_layouts` are the groups before the histogram's own and `shader` the fractal
+    /// shader with the cache and histogram sources appended.
+    pub fn new(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("histogram_bind_group_layout"),
+        });
+
+        let mut layouts = bind_group_layouts.to_vec();
+        layouts.push(&bind_group_layout);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Histogram Pipeline Layout"),
+            bind_group_layouts: &layouts,
+            push_constant_ranges: &[],
+        });
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Histogram Buffer"),
+            size: ((2 + HISTOGRAM_BINS) * 4) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            range_pipeline: create_compute_pipeline(device, &pipeline_layout, shader, "cs_range"),
+            bins_pipeline: create_compute_pipeline(device, &pipeline_layout, shader, "cs_bins"),
+            cumulate_pipeline: create_compute_pipeline(
+                device,
+                &pipeline_layout,
+                shader,
+                "cs_cumulate",
+            ),
+            bind_group_layout,
+            pipeline_layout,
+            buffer,
+        }
+    }
+
+    /// Rebuild the pipelines from `shader`, after its custom formula changed.
+    pub fn set_shader(&mut self, device: &wgpu::Device, shader: &wgpu::ShaderModule) {
+        let layout = &self.pipeline_layout;
+        self.range_pipeline = create_compute_pipeline(device, layout, shader, "cs_range");
+        self.bins_pipeline = create_compute_pipeline(device, layout, shader, "cs_bins");
+        self.cumulate_pipeline = create_compute_pipeline(device, layout, shader, "cs_cumulate");
+    }
+
+    /// The histogram's group, counting the points of `point_escape`.
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        point_escape: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(point_escape),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("histogram_bind_group"),
+        })
+    }
+
+    /// Record emptying the histogram, before the compute pass `count` goes in.
+    pub fn clear(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.clear_buffer(&self.buffer, 0, None);
+    }
+
+    /// Record counting a `width` by `height` cache into the histogram, with the groups before
+    /// the histogram's own already set on `compute_pass`.
+    pub fn count<'a>(
+        &'a self,
+        compute_pass: &mut wgpu::ComputePass<'a>,
+        bind_group: &'a wgpu::BindGroup,
+        group: u32,
+        width: u32,
+        height: u32,
+    ) {
+        let workgroups = [
+            width.div_ceil(WORKGROUP_SIZE),
+            height.div_ceil(WORKGROUP_SIZE),
+        ];
+        compute_pass.set_bind_group(group, bind_group, &[]);
+
+        compute_pass.set_pipeline(&self.range_pipeline);
+        compute_pass.dispatch_workgroups(workgroups[0], workgroups[1], 1);
+        compute_pass.set_pipeline(&self.bins_pipeline);
+        compute_pass.dispatch_workgroups(workgroups[0], workgroups[1], 1);
+        compute_pass.set_pipeline(&self.cumulate_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+}
@@ -1,4 +1,22 @@
+pub mod bigfloat;
+pub mod cache;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
+pub mod cpu;
+pub mod expression;
+pub mod fractal;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod headless;
+pub mod histogram;
+pub mod palette;
+pub mod perturbation;
+pub mod polynomial;
+pub mod refinement;
+pub mod renderer;
+pub mod sampling;
+pub mod scene;
 pub mod state;
 pub mod uniform;
 pub mod vertex;
+pub mod viewport;
 pub mod window;
@@ -1,7 +1,8 @@
 #![windows_subsystem = "windows"] // Remove this to use console for debugging
 
-use fractal_explorer::window;
+use clap::Parser;
+use fractal_explorer::cli::Cli;
 
 fn main() {
-    pollster::block_on(window::run());
+    Cli::parse().run();
 }
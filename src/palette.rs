@@ -0,0 +1,687 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// Number of colours in the texture a palette is sampled into.
+pub const PALETTE_WIDTH: u32 = 256;
+
+/// A colour gradient, sampled at `PALETTE_WIDTH` evenly spaced points.
+///
+/// The gradient wraps around, the last colour blends back into the first one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<[f32; 3]>,
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// Malformed file, with the line the problem was found at.
+    Parse {
+        line: usize,
+        message: String,
+    },
+    UnknownFormat(String),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::Io(error) => write!(f, "{}", error),
+            PaletteError::Json(error) => write!(f, "invalid palette JSON: {}", error),
+            PaletteError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            PaletteError::UnknownFormat(extension) => {
+                write!(
+                    f,
+                    "unknown palette format '{}', expected ggr, map or json",
+                    extension
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<std::io::Error> for PaletteError {
+    fn from(error: std::io::Error) -> Self {
+        PaletteError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for PaletteError {
+    fn from(error: serde_json::Error) -> Self {
+        PaletteError::Json(error)
+    }
+}
+
+/// Simple gradient file, a list of colours at positions from 0.0 to 1.0.
+///
+/// `{"name": "Fire", "stops": [{"position": 0.0, "color": "#000000"}, ...]}`
+#[derive(Deserialize)]
+struct JsonPalette {
+    #[serde(default)]
+    name: Option<String>,
+    stops: Vec<JsonStop>,
+}
+
+#[derive(Deserialize)]
+struct JsonStop {
+    position: f32,
+    color: String,
+}
+
+impl Palette {
+    /// Linear interpolation between colours at positions from 0.0 to 1.0.
+    pub fn from_stops(name: &str, stops: &[(f32, [f32; 3])]) -> Self {
+        let mut stops = stops.to_vec();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let colors = (0..PALETTE_WIDTH)
+            .map(|i| {
+                let position = i as f32 / PALETTE_WIDTH as f32;
+                let after = stops.iter().position(|stop| stop.0 > position);
+                // wrap around between the last and the first stop
+                let (first, last) = (stops[0], stops[stops.len() - 1]);
+                let (before, after) = match after {
+                    Some(0) => ((last.0 - 1.0, last.1), first),
+                    None => (last, (first.0 + 1.0, first.1)),
+                    Some(after) => (stops[after - 1], stops[after]),
+                };
+                let t = if after.0 > before.0 {
+                    (position - before.0) / (after.0 - before.0)
+                } else {
+                    0.0
+                };
+                mix(before.1, after.1, t.clamp(0.0, 1.0))
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            colors,
+        }
+    }
+
+    /// Load a palette, the format is taken from the file extension.
+    pub fn load(path: &Path) -> Result<Self, PaletteError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        let contents = std::fs::read_to_string(path)?;
+        match extension.as_str() {
+            "ggr" => Self::from_ggr(name, &contents),
+            "map" => Self::from_map(name, &contents),
+            "json" => Self::from_json(name, &contents),
+            _ => Err(PaletteError::UnknownFormat(extension)),
+        }
+    }
+
+    /// Simple JSON stop list, see `JsonPalette`. `name` is used if the file has none.
+    pub fn from_json(name: &str, contents: &str) -> Result<Self, PaletteError> {
+        let palette: JsonPalette = serde_json::from_str(contents)?;
+        if palette.stops.is_empty() {
+            return Err(PaletteError::Parse {
+                line: 1,
+                message: "no stops".to_string(),
+            });
+        }
+        let positions = palette.stops.iter().map(|stop| stop.position);
+        let in_range = positions
+            .clone()
+            .all(|position| (0.0..=1.0).contains(&position));
+        let sorted = positions
+            .clone()
+            .zip(positions.skip(1))
+            .all(|(a, b)| a <= b);
+        if !(in_range && sorted) {
+            return Err(PaletteError::Parse {
+                line: 1,
+                message: "stop positions must go up from 0.0 to 1.0".to_string(),
+            });
+        }
+        let stops = palette
+            .stops
+            .iter()
+            .map(|stop| {
+                parse_hex_color(&stop.color)
+                    .map(|color| (stop.position, color))
+                    .ok_or_else(|| PaletteError::Parse {
+                        line: 1,
+                        message: format!("invalid color '{}', expected #rrggbb", stop.color),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::from_stops(
+            palette.name.as_deref().unwrap_or(name),
+            &stops,
+        ))
+    }
+
+    /// Fractint `.map` file, one `red green blue` line (0 to 255) per colour,
+    /// anything after the third number is a comment.
+    pub fn from_map(name: &str, contents: &str) -> Result<Self, PaletteError> {
+        let mut colors = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let color: Vec<f32> = line
+                .split_whitespace()
+                .take(3)
+                .map(|value| value.parse::<u8>().map(|value| value as f32 / 255.0))
+                .collect::<Result<_, _>>()
+                .map_err(|error| PaletteError::Parse {
+                    line: index + 1,
+                    message: error.to_string(),
+                })?;
+            if color.len() != 3 {
+                return Err(PaletteError::Parse {
+                    line: index + 1,
+                    message: "expected three color components".to_string(),
+                });
+            }
+            colors.push([color[0], color[1], color[2]]);
+        }
+        if colors.is_empty() {
+            return Err(PaletteError::Parse {
+                line: 1,
+                message: "no colors".to_string(),
+            });
+        }
+        let stops: Vec<(f32, [f32; 3])> = colors
+            .iter()
+            .enumerate()
+            .map(|(i, color)| (i as f32 / colors.len() as f32, *color))
+            .collect();
+        Ok(Self::from_stops(name, &stops))
+    }
+
+    /// GIMP `.ggr` gradient, a list of segments each blending between two colours.
+    /// `name` is used if the file has none.
+    pub fn from_ggr(name: &str, contents: &str) -> Result<Self, PaletteError> {
+        let mut lines = contents.lines().enumerate();
+        let error = |line: usize, message: &str| PaletteError::Parse {
+            line: line + 1,
+            message: message.to_string(),
+        };
+
+        match lines.next() {
+            Some((_, header)) if header.trim() == "GIMP Gradient" => {}
+            _ => return Err(error(0, "missing 'GIMP Gradient' header")),
+        }
+        let (mut index, mut line) = lines.next().ok_or_else(|| error(1, "unexpected end"))?;
+        let mut name = name.to_string();
+        if let Some(value) = line.strip_prefix("Name:") {
+            name = value.trim().to_string();
+            (index, line) = lines.next().ok_or_else(|| error(2, "unexpected end"))?;
+        }
+        let count: usize = line
+            .trim()
+            .parse()
+            .map_err(|_| error(index, "expected number of segments"))?;
+        // the count comes from the file, so it is not trusted further than its lines go
+        if count > lines.clone().count() {
+            return Err(error(index, "more segments than lines"));
+        }
+
+        let mut segments = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (index, line) = lines
+                .next()
+                .ok_or_else(|| error(index, "missing segment"))?;
+            let values: Vec<f32> = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| error(index, "invalid number"))?;
+            if values.len() < 13 {
+                return Err(error(index, "expected at least 13 values per segment"));
+            }
+            segments.push(GgrSegment {
+                left: values[0],
+                middle: values[1],
+                right: values[2],
+                color_left: [values[3], values[4], values[5]],
+                color_right: [values[7], values[8], values[9]],
+                blending: values[11] as u32,
+                coloring: values[12] as u32,
+            });
+        }
+        if segments.is_empty() {
+            return Err(error(index, "no segments"));
+        }
+
+        let colors = (0..PALETTE_WIDTH)
+            .map(|i| {
+                let position = (i as f32 + 0.5) / PALETTE_WIDTH as f32;
+                let segment = segments
+                    .iter()
+                    .find(|segment| position <= segment.right)
+                    .unwrap_or(&segments[segments.len() - 1]);
+                segment.color_at(position)
+            })
+            .collect();
+        Ok(Self { name, colors })
+    }
+
+    /// The palettes available without loading any file.
+    pub fn library() -> Vec<Self> {
+        vec![
+            Self::from_stops(
+                "Ultra",
+                &[
+                    (0.0, rgb(0, 7, 100)),
+                    (0.16, rgb(32, 107, 203)),
+                    (0.42, rgb(237, 255, 255)),
+                    (0.6425, rgb(255, 170, 0)),
+                    (0.8575, rgb(0, 2, 0)),
+                ],
+            ),
+            Self::from_stops(
+                "Fire",
+                &[
+                    (0.0, rgb(0, 0, 0)),
+                    (0.25, rgb(128, 0, 0)),
+                    (0.5, rgb(255, 96, 0)),
+                    (0.75, rgb(255, 224, 64)),
+                    (0.9, rgb(255, 255, 224)),
+                ],
+            ),
+            Self::from_stops(
+                "Ocean",
+                &[
+                    (0.0, rgb(0, 16, 32)),
+                    (0.3, rgb(0, 96, 128)),
+                    (0.55, rgb(64, 200, 208)),
+                    (0.7, rgb(240, 248, 255)),
+                    (0.85, rgb(16, 64, 112)),
+                ],
+            ),
+            Self::from_stops(
+                "Rainbow",
+                &(0..6)
+                    .map(|i| (i as f32 / 6.0, hsv_to_rgb([i as f32 / 6.0, 1.0, 1.0])))
+                    .collect::<Vec<_>>(),
+            ),
+            Self::from_stops(
+                "Grayscale",
+                &[(0.0, rgb(0, 0, 0)), (0.5, rgb(255, 255, 255))],
+            ),
+        ]
+    }
+
+    pub fn to_rgba8(&self) -> Vec<[u8; 4]> {
+        self.colors
+            .iter()
+            .map(|color| {
+                let [r, g, b] = color.map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+}
+
+struct GgrSegment {
+    left: f32,
+    middle: f32,
+    right: f32,
+    color_left: [f32; 3],
+    color_right: [f32; 3],
+    /// 0 linear, 1 curved, 2 sine, 3 sphere increasing, 4 sphere decreasing, 5 step.
+    blending: u32,
+    /// 0 RGB, 1 HSV counterclockwise, 2 HSV clockwise.
+    coloring: u32,
+}
+
+impl GgrSegment {
+    /// Same blending as GIMP, see `gimp_gradient_get_color_at`.
+    fn color_at(&self, position: f32) -> [f32; 3] {
+        let length = self.right - self.left;
+        let (position, middle) = if length > 0.0 {
+            (
+                (position - self.left) / length,
+                (self.middle - self.left) / length,
+            )
+        } else {
+            (0.5, 0.5)
+        };
+        let linear = if position <= middle {
+            if middle > 0.0 {
+                0.5 * position / middle
+            } else {
+                0.0
+            }
+        } else if middle < 1.0 {
+            0.5 + 0.5 * (position - middle) / (1.0 - middle)
+        } else {
+            1.0
+        };
+        let factor = match self.blending {
+            1 => {
+                if middle > 0.0 {
+                    position.powf(0.5f32.ln() / middle.max(1e-10).ln())
+                } else {
+                    0.0
+                }
+            }
+            2 => ((-std::f32::consts::FRAC_PI_2 + std::f32::consts::PI * linear).sin() + 1.0) / 2.0,
+            3 => (1.0 - (linear - 1.0) * (linear - 1.0)).sqrt(),
+            4 => 1.0 - (1.0 - linear * linear).sqrt(),
+            5 => {
+                if position >= middle {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            _ => linear,
+        };
+
+        match self.coloring {
+            1 | 2 => {
+                let [h0, s0, v0] = rgb_to_hsv(self.color_left);
+                let [h1, s1, v1] = rgb_to_hsv(self.color_right);
+                let mut delta = h1 - h0;
+                if self.coloring == 1 && delta < 0.0 {
+                    delta += 1.0;
+                } else if self.coloring == 2 && delta > 0.0 {
+                    delta -= 1.0;
+                }
+                let hue = (h0 + delta * factor).rem_euclid(1.0);
+                hsv_to_rgb([hue, s0 + (s1 - s0) * factor, v0 + (v1 - v0) * factor])
+            }
+            _ => mix(self.color_left, self.color_right, factor),
+        }
+    }
+}
+
+fn rgb(r: u8, g: u8, b: u8) -> [f32; 3] {
+    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0]
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
+
+fn parse_hex_color(color: &str) -> Option<[f32; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let component = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(rgb(component(0)?, component(2)?, component(4)?))
+}
+
+/// Hue, saturation and value all from 0.0 to 1.0.
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    let saturation = if max > 0.0 { chroma / max } else { 0.0 };
+    [hue / 6.0, saturation, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let h = h.rem_euclid(1.0) * 6.0;
+    let chroma = v * s;
+    let x = chroma * (1.0 - ((h % 2.0) - 1.0).abs());
+    let m = v - chroma;
+    let [r, g, b] = if h < 1.0 {
+        [chroma, x, 0.0]
+    } else if h < 2.0 {
+        [x, chroma, 0.0]
+    } else if h < 3.0 {
+        [0.0, chroma, x]
+    } else if h < 4.0 {
+        [0.0, x, chroma]
+    } else if h < 5.0 {
+        [x, 0.0, chroma]
+    } else {
+        [chroma, 0.0, x]
+    };
+    [r + m, g + m, b + m]
+}
+
+/// GPU copy of a `Palette`, a 1D gradient bound next to the uniform.
+///
+/// Stored as a single row 2D texture since WebGL2 has no 1D textures.
+pub struct PaletteTexture {
+    texture: wgpu::Texture,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl PaletteTexture {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, palette: &Palette) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Palette Texture"),
+            size: wgpu::Extent3d {
+                width: PALETTE_WIDTH,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // repeat so the gradient cycles, linear so neighbouring colours blend
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Palette Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("palette_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("palette_bind_group"),
+        });
+
+        let palette_texture = Self {
+            texture,
+            bind_group_layout,
+            bind_group,
+        };
+        palette_texture.write(queue, palette);
+        palette_texture
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, palette: &Palette) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&palette.to_rgba8()),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(PALETTE_WIDTH * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: PALETTE_WIDTH,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGR: &str = "GIMP Gradient
+Name: Test
+2
+0.0 0.25 0.5 0 0 0 1 1 1 1 1 0 0
+0.5 0.75 1.0 1 1 1 1 1 0 0 1 0 0
+";
+
+    fn assert_color(color: [f32; 3], expected: [f32; 3]) {
+        let distance = (0..3)
+            .map(|i| (color[i] - expected[i]).abs())
+            .fold(0.0, f32::max);
+        assert!(distance < 0.01, "{:?} is not {:?}", color, expected);
+    }
+
+    fn assert_parse_error(result: Result<Palette, PaletteError>, expected_line: usize) {
+        match result {
+            Err(PaletteError::Parse { line, .. }) => assert_eq!(line, expected_line),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_ggr() {
+        let palette = Palette::from_ggr("Gradient", GGR).unwrap();
+        assert_eq!(palette.name, "Test");
+        assert_eq!(palette.colors.len(), PALETTE_WIDTH as usize);
+        assert_color(palette.colors[0], [0.0, 0.0, 0.0]);
+        assert_color(palette.colors[64], [0.5, 0.5, 0.5]);
+        assert_color(palette.colors[128], [1.0, 1.0, 1.0]);
+        assert_color(palette.colors[255], [1.0, 0.0, 0.0]);
+        // the name is optional
+        let unnamed = Palette::from_ggr("Gradient", &GGR.replace("Name: Test\n", "")).unwrap();
+        assert_eq!(unnamed.name, "Gradient");
+        assert_eq!(unnamed.colors, palette.colors);
+    }
+
+    #[test]
+    fn rejects_malformed_ggr() {
+        assert_parse_error(Palette::from_ggr("Gradient", ""), 1);
+        assert_parse_error(Palette::from_ggr("Gradient", "GIMP Palette\n1\n"), 1);
+        assert_parse_error(
+            Palette::from_ggr("Gradient", "GIMP Gradient\nName: Test\n"),
+            3,
+        );
+        assert_parse_error(
+            Palette::from_ggr("Gradient", &GGR.replace("\n2\n", "\ntwo\n")),
+            3,
+        );
+        assert_parse_error(
+            Palette::from_ggr("Gradient", &GGR.replace("\n2\n", "\n0\n")),
+            3,
+        );
+        // a count the file cannot hold fails before anything is allocated for it
+        let huge = GGR.replace("\n2\n", &format!("\n{}\n", usize::MAX));
+        assert_parse_error(Palette::from_ggr("Gradient", &huge), 3);
+        assert_parse_error(
+            Palette::from_ggr("Gradient", &GGR.replace("\n2\n", "\n3\n")),
+            3,
+        );
+        assert_parse_error(
+            Palette::from_ggr("Gradient", &GGR.replace(" 1 0 0\n", " 1\n")),
+            4,
+        );
+        assert_parse_error(Palette::from_ggr("Gradient", &GGR.replace("0.75", "x")), 5);
+    }
+
+    #[test]
+    fn reads_map() {
+        let palette = Palette::from_map("Map", "0 0 0 black\n\n255 255 255\n").unwrap();
+        assert_eq!(palette.name, "Map");
+        assert_color(palette.colors[0], [0.0, 0.0, 0.0]);
+        assert_color(palette.colors[128], [1.0, 1.0, 1.0]);
+        assert_color(palette.colors[64], [0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn rejects_malformed_map() {
+        assert_parse_error(Palette::from_map("Map", ""), 1);
+        assert_parse_error(Palette::from_map("Map", "0 0 0\n0 0\n"), 2);
+        assert_parse_error(Palette::from_map("Map", "0 0 0\n0 256 0\n"), 2);
+        assert_parse_error(Palette::from_map("Map", "red green blue\n"), 1);
+    }
+
+    #[test]
+    fn reads_json() {
+        let contents = r##"{"stops": [{"position": 0.0, "color": "#ff0000"},
+            {"position": 0.5, "color": "#0000ff"}]}"##;
+        let palette = Palette::from_json("Json", contents).unwrap();
+        assert_eq!(palette.name, "Json");
+        assert_color(palette.colors[0], [1.0, 0.0, 0.0]);
+        assert_color(palette.colors[128], [0.0, 0.0, 1.0]);
+        let named = contents.replacen('{', r#"{"name": "Named", "#, 1);
+        assert_eq!(Palette::from_json("Json", &named).unwrap().name, "Named");
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        assert!(matches!(
+            Palette::from_json("Json", "{\"stops\": "),
+            Err(PaletteError::Json(_))
+        ));
+        assert_parse_error(Palette::from_json("Json", r#"{"stops": []}"#), 1);
+        let contents = r#"{"stops": [{"position": 0.0, "color": "red"}]}"#;
+        assert_parse_error(Palette::from_json("Json", contents), 1);
+        let stop = |position: f32| format!(r##"{{"position": {}, "color": "#000000"}}"##, position);
+        for positions in [[0.0, 1.5], [-0.5, 0.5], [0.5, 0.25]] {
+            let stops: Vec<String> = positions.into_iter().map(stop).collect();
+            let contents = format!(r#"{{"stops": [{}]}}"#, stops.join(", "));
+            assert_parse_error(Palette::from_json("Json", &contents), 1);
+        }
+    }
+}
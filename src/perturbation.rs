@@ -0,0 +1,168 @@
+use crate::bigfloat::BigFloat;
+use crate::fractal::Formula;
+
+/// Width of the texture holding the reference orbit, one point per texel.
+/// Kept within `Limits::downlevel_webgl2_defaults().max_texture_dimension_2d`.
+pub const ORBIT_TEXTURE_WIDTH: u32 = 1024;
+
+/// Squared radius after which the reference orbit is considered to have escaped,
+/// must match the largest bailout in `shader.wgsl` (the one for smooth colouring).
+pub const BAILOUT_SQUARED: f64 = 65536.0;
+
+/// Orbit of a single point computed in high precision on the CPU.
+///
+/// The shader only iterates the difference between each pixel and this orbit,
+/// which is small enough to be tracked in `f32` (with a separate exponent).
+pub struct ReferenceOrbit {
+    pub points: Vec<[f32; 2]>,
+}
+
+impl ReferenceOrbit {
+    /// Iterate `z -> f(z) + c` for the formula raised to the integer `power`, starting at
+    /// `z_start` with `bits` bits of precision. Stops after `iterations_max` iterations or
+    /// right after the orbit escapes.
+    pub fn compute(
+        formula: Formula,
+        power: u32,
+        z_start: &[BigFloat; 2],
+        c: &[BigFloat; 2],
+        iterations_max: u32,
+        bits: u64,
+    ) -> Self {
+        let mut points = Vec::with_capacity(iterations_max as usize + 1);
+        let [mut x, mut y] = [z_start[0].round(bits), z_start[1].round(bits)];
+        for _ in 0..=iterations_max {
+            let (x_f64, y_f64) = (x.to_f64(), y.to_f64());
+            points.push([x_f64 as f32, y_f64 as f32]);
+            if x_f64 * x_f64 + y_f64 * y_f64 > BAILOUT_SQUARED {
+                break;
+            }
+            // same as apply_formula in shader.wgsl
+            let before = match formula {
+                Formula::BurningShip => [x.abs(), y.abs()],
+                Formula::Tricorn => [x, y.neg()],
+                Formula::Perpendicular => [x.abs(), y.neg()],
+                _ => [x, y],
+            };
+            let mut w = before.clone();
+            for _ in 1..power {
+                let [re, im] = &w;
+                w = [
+                    re.mul(&before[0]).sub(&im.mul(&before[1])).round(bits),
+                    re.mul(&before[1]).add(&im.mul(&before[0])).round(bits),
+                ];
+            }
+            let [re, im] = match formula {
+                Formula::Celtic => [w[0].abs(), w[1].clone()],
+                Formula::Buffalo => [w[0].abs(), w[1].abs().neg()],
+                _ => w,
+            };
+            x = re.add(&c[0]).round(bits);
+            y = im.add(&c[1]).round(bits);
+        }
+        Self { points }
+    }
+
+    pub fn len(&self) -> u32 {
+        self.points.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+/// GPU copy of a `ReferenceOrbit`, bound next to the uniform.
+///
+/// A texture is used instead of a storage buffer since WebGL2 has no storage buffers.
+pub struct OrbitTexture {
+    texture: wgpu::Texture,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl OrbitTexture {
+    pub fn new(device: &wgpu::Device, capacity: u32) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+            label: Some("orbit_bind_group_layout"),
+        });
+        let (texture, bind_group) = Self::create(device, &bind_group_layout, capacity);
+        Self {
+            texture,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        capacity: u32,
+    ) -> (wgpu::Texture, wgpu::BindGroup) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Orbit Texture"),
+            size: wgpu::Extent3d {
+                width: ORBIT_TEXTURE_WIDTH,
+                height: capacity.div_ceil(ORBIT_TEXTURE_WIDTH).max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rg32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+            label: Some("orbit_bind_group"),
+        });
+        (texture, bind_group)
+    }
+
+    /// Upload the orbit, growing the texture if it does not fit.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, orbit: &ReferenceOrbit) {
+        let rows = orbit.len().div_ceil(ORBIT_TEXTURE_WIDTH).max(1);
+        if rows > self.texture.height() {
+            (self.texture, self.bind_group) =
+                Self::create(device, &self.bind_group_layout, orbit.len());
+        }
+
+        let mut data = orbit.points.clone();
+        data.resize((rows * ORBIT_TEXTURE_WIDTH) as usize, [0.0, 0.0]);
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&data),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(ORBIT_TEXTURE_WIDTH * 8),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: ORBIT_TEXTURE_WIDTH,
+                height: rows,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Highest degree the shader has room for, see `Uniform::polynomial`.
+pub const POLYNOMIAL_DEGREE_MAX: usize = 8;
+
+/// Iterations of the root finder, it converges in far fewer for any polynomial that fits.
+const ROOT_ITERATIONS_MAX: u32 = 1000;
+
+/// Polynomial with complex coefficients, the one Newton's method draws the basins of.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polynomial {
+    /// From the highest power of `z` down to the constant term, the first one is not zero.
+    coefficients: Vec<[f64; 2]>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PolynomialError {
+    /// Degree after dropping leading zero coefficients.
+    Degree(usize),
+    NotFinite,
+    /// A coefficient that is not a number, in `FromStr`.
+    Parse(String),
+}
+
+impl fmt::Display for PolynomialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolynomialError::Degree(degree) => write!(
+                f,
+                "polynomial degree must be between 2 and {}, got {}",
+                POLYNOMIAL_DEGREE_MAX, degree
+            ),
+            PolynomialError::NotFinite => write!(f, "polynomial coefficients must be finite"),
+            PolynomialError::Parse(value) => write!(f, "invalid coefficient '{}'", value),
+        }
+    }
+}
+
+impl std::error::Error for PolynomialError {}
+
+impl Default for Polynomial {
+    /// `z^3 - 1`, whose roots are the cube roots of unity.
+    fn default() -> Self {
+        Self {
+            coefficients: vec![[1.0, 0.0], [0.0, 0.0], [0.0, 0.0], [-1.0, 0.0]],
+        }
+    }
+}
+
+impl Polynomial {
+    /// `coefficients` from the highest power of `z` down, leading zeros are dropped.
+    pub fn new(coefficients: &[[f64; 2]]) -> Result<Self, PolynomialError> {
+        if coefficients.iter().flatten().any(|part| !part.is_finite()) {
+            return Err(PolynomialError::NotFinite);
+        }
+        let leading_zeros = coefficients
+            .iter()
+            .take_while(|coefficient| **coefficient == [0.0, 0.0])
+            .count();
+        let coefficients = coefficients[leading_zeros..].to_vec();
+        let degree = coefficients.len().saturating_sub(1);
+        if !(2..=POLYNOMIAL_DEGREE_MAX).contains(&degree) {
+            return Err(PolynomialError::Degree(degree));
+        }
+        Ok(Self { coefficients })
+    }
+
+    pub fn get_coefficients(&self) -> &[[f64; 2]] {
+        &self.coefficients
+    }
+
+    pub fn get_degree(&self) -> usize {
+        self.coefficients.len() - 1
+    }
+
+    /// All roots, repeated ones as many times as they repeat, sorted by their angle
+    /// so that the colour of each basin does not depend on how they were found.
+    ///
+    /// Durand-Kerner iteration: every estimate moves by the value of the monic polynomial
+    /// divided by the product of its distances to the other estimates.
+    pub fn get_roots(&self) -> Vec<[f64; 2]> {
+        let leading = self.coefficients[0];
+        let monic: Vec<[f64; 2]> = self
+            .coefficients
+            .iter()
+            .map(|coefficient| div(*coefficient, leading))
+            .collect();
+        let evaluate = |z: [f64; 2]| {
+            monic.iter().fold([0.0, 0.0], |value, coefficient| {
+                add(mul(value, z), *coefficient)
+            })
+        };
+
+        // powers of a number that is neither real nor a root of unity
+        let seed = [0.4, 0.9];
+        let mut roots = vec![[1.0, 0.0]; self.get_degree()];
+        for i in 1..roots.len() {
+            roots[i] = mul(roots[i - 1], seed);
+        }
+
+        for _ in 0..ROOT_ITERATIONS_MAX {
+            let mut change: f64 = 0.0;
+            for i in 0..roots.len() {
+                let denominator = (0..roots.len())
+                    .filter(|j| *j != i)
+                    .fold([1.0, 0.0], |product, j| {
+                        mul(product, sub(roots[i], roots[j]))
+                    });
+                if denominator == [0.0, 0.0] {
+                    continue;
+                }
+                let step = div(evaluate(roots[i]), denominator);
+                roots[i] = sub(roots[i], step);
+                change = change.max(step[0].hypot(step[1]));
+            }
+            if change < 1e-15 {
+                break;
+            }
+        }
+
+        roots.sort_by(|a, b| a[1].atan2(a[0]).total_cmp(&b[1].atan2(b[0])));
+        roots
+    }
+}
+
+impl fmt::Display for Polynomial {
+    /// Comma separated coefficients, as read by `FromStr`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self
+            .coefficients
+            .iter()
+            .map(|[re, im]| {
+                if *im == 0.0 {
+                    format!("{}", re)
+                } else {
+                    format!("{}:{}", re, im)
+                }
+            })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl FromStr for Polynomial {
+    type Err = PolynomialError;
+
+    /// Comma separated coefficients from the highest power down, `RE` or `RE:IM`,
+    /// so `1,0,0,-1` is `z^3 - 1`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| PolynomialError::Parse(value.trim().to_string()))
+        };
+        let coefficients = value
+            .split(',')
+            .map(|coefficient| match coefficient.split_once(':') {
+                Some((re, im)) => Ok([parse(re)?, parse(im)?]),
+                None => Ok([parse(coefficient)?, 0.0]),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(&coefficients)
+    }
+}
+
+fn add(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn mul(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]]
+}
+
+fn div(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
+    let norm = b[0] * b[0] + b[1] * b[1];
+    [
+        (a[0] * b[0] + a[1] * b[1]) / norm,
+        (a[1] * b[0] - a[0] * b[1]) / norm,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(polynomial: &Polynomial, expected: &[[f64; 2]]) {
+        let roots = polynomial.get_roots();
+        assert_eq!(roots.len(), expected.len());
+        for (root, expected) in roots.iter().zip(expected) {
+            let distance = (root[0] - expected[0]).hypot(root[1] - expected[1]);
+            assert!(distance < 1e-9, "{:?} is not {:?}", root, expected);
+        }
+    }
+
+    #[test]
+    fn finds_cube_roots_of_unity() {
+        let half = 3f64.sqrt() / 2.0;
+        assert_roots(
+            &Polynomial::default(),
+            &[[-0.5, -half], [1.0, 0.0], [-0.5, half]],
+        );
+    }
+
+    #[test]
+    fn finds_complex_roots() {
+        // (z - 2)(z - i) = z^2 - (2 + i)z + 2i
+        let polynomial: Polynomial = "1,-2:-1,0:2".parse().unwrap();
+        assert_roots(&polynomial, &[[2.0, 0.0], [0.0, 1.0]]);
+        // leading zeros are dropped, 2z^2 - 8 has roots -2 and 2
+        let polynomial = Polynomial::new(&[[0.0, 0.0], [2.0, 0.0], [0.0, 0.0], [-8.0, 0.0]]);
+        assert_roots(&polynomial.unwrap(), &[[2.0, 0.0], [-2.0, 0.0]]);
+    }
+
+    #[test]
+    fn round_trips_through_strings() {
+        let polynomial: Polynomial = "1,0:0.5,0,-1".parse().unwrap();
+        assert_eq!(polynomial.to_string().parse::<Polynomial>(), Ok(polynomial));
+    }
+
+    #[test]
+    fn rejects_invalid_polynomials() {
+        assert_eq!("1,2".parse::<Polynomial>(), Err(PolynomialError::Degree(1)));
+        assert_eq!(
+            "1,0,0,0,0,0,0,0,0,1".parse::<Polynomial>(),
+            Err(PolynomialError::Degree(9))
+        );
+        assert_eq!(
+            "1,x,1".parse::<Polynomial>(),
+            Err(PolynomialError::Parse("x".to_string()))
+        );
+        assert_eq!(
+            Polynomial::new(&[[1.0, 0.0], [f64::NAN, 0.0], [1.0, 0.0]]),
+            Err(PolynomialError::NotFinite)
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use crate::cache::iteration_key;
+use crate::sampling::Sampling;
+use crate::uniform::Uniform;
+
+/// Times smaller than the window, in each direction, previews are rendered.
+const PREVIEW_DIVISOR: u32 = 4;
+
+/// How far frames are refined while the view stays still. Each change to the view starts over
+/// from a quick preview, then every frame adds a sample of each pixel to the average of those
+/// before it, see `Sampling`. While only the palette moves, the last sample is coloured again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refinement {
+    /// A fraction of the resolution, scaled up.
+    Preview,
+    /// Sample `index` of each pixel, averaged with the samples before it.
+    Sample(u32),
+    /// All samples are averaged, frames show the average without rendering.
+    Finished,
+    /// The last sample taken, coloured with a palette that moved. It is taken at the same
+    /// offset, so that its points come from the iteration cache and are not iterated again.
+    Cycling,
+}
+
+/// `uniform` without the fields that change on every frame or sample, to tell what changed
+/// between frames.
+pub fn get_refinement_key(uniform: &Uniform) -> Uniform {
+    Uniform {
+        time: 0.0,
+        mouse: [0.0, 0.0],
+        sample_offset: [0.0, 0.0],
+        // follows from the view, set by prepare
+        reference_length: 0,
+        ..*uniform
+    }
+}
+
+impl Refinement {
+    /// The stage after this one when the frame before was drawn with `previous` and the next
+    /// one is drawn with `key`, both as from `get_refinement_key`, and every pixel takes
+    /// `samples` samples. `None` starts over from a preview.
+    pub fn advance(self, previous: Option<&Uniform>, key: &Uniform, samples: u32) -> Refinement {
+        match previous {
+            // what has to be iterated again is previewed, what only has to be coloured again
+            // starts over from the first sample, and a moving palette colours the last one
+            Some(previous) if iteration_key(previous) != iteration_key(key) => Refinement::Preview,
+            Some(previous)
+                if *previous
+                    != Uniform {
+                        palette_offset: previous.palette_offset,
+                        ..*key
+                    } =>
+            {
+                Refinement::Sample(0)
+            }
+            Some(previous) if previous.palette_offset != key.palette_offset => self.cycle(),
+            Some(_) => self.next(samples),
+            None => Refinement::Preview,
+        }
+    }
+
+    /// The stage after this one, when every pixel takes `samples` samples.
+    pub fn next(self, samples: u32) -> Refinement {
+        match self {
+            Refinement::Preview => Refinement::Sample(0),
+            Refinement::Sample(index) if index + 1 < samples => Refinement::Sample(index + 1),
+            Refinement::Sample(_) | Refinement::Finished => Refinement::Finished,
+            // the average holds colours of the palette before, so it starts over
+            Refinement::Cycling => Refinement::Sample(0),
+        }
+    }
+
+    /// The stage after this one when only the palette moved.
+    pub fn cycle(self) -> Refinement {
+        match self {
+            // previews are not taken at full size, so a first sample is
+            Refinement::Preview => Refinement::Sample(0),
+            Refinement::Sample(_) | Refinement::Finished | Refinement::Cycling => {
+                Refinement::Cycling
+            }
+        }
+    }
+
+    /// Offset of the sample rendered at this stage, `previous` being that of the stage before.
+    pub fn get_sample_offset(self, sampling: &Sampling, previous: [f32; 2]) -> [f32; 2] {
+        match self {
+            Refinement::Preview => [0.0, 0.0],
+            Refinement::Sample(index) => sampling.get_accumulated_offset(index),
+            // the last sample, whose points are cached
+            Refinement::Finished | Refinement::Cycling => previous,
+        }
+    }
+
+    /// Weight in the average of the sample rendered at this stage. Previews, first samples
+    /// and the colourings of a moving palette replace the average.
+    pub fn get_weight(self) -> f32 {
+        match self {
+            Refinement::Sample(index) => 1.0 / (index + 1) as f32,
+            Refinement::Preview | Refinement::Finished | Refinement::Cycling => 1.0,
+        }
+    }
+
+    /// Size to render at for a `width` by `height` window.
+    pub fn get_size(self, width: u32, height: u32) -> [u32; 2] {
+        match self {
+            Refinement::Preview => [
+                width.div_ceil(PREVIEW_DIVISOR),
+                height.div_ceil(PREVIEW_DIVISOR),
+            ],
+            Refinement::Sample(_) | Refinement::Finished | Refinement::Cycling => [width, height],
+        }
+    }
+}
+
+/// Shader source of the stages scaling and averaging frames.
+const BLIT_SOURCE: &str = include_str!("shaders/blit.wgsl");
+
+/// Render target of frames not drawn straight to the output, with the pipelines scaling it
+/// over the output and averaging its samples.
+pub struct Blit {
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    /// Draws the render target or the average over the output.
+    pipeline: wgpu::RenderPipeline,
+    /// Blends a sample into the average, weighted by the blend constant.
+    accumulate_pipeline: wgpu::RenderPipeline,
+    /// Format of the render target and of the output.
+    format: wgpu::TextureFormat,
+    /// Format of the average, with more precision than `format` where the adapter can
+    /// blend it, so that many samples do not round away.
+    accumulation_format: wgpu::TextureFormat,
+    /// Created on first use and again when the size changes.
+    target: Option<BlitTexture>,
+    /// Average of the samples so far, the size of `target` when last accumulated.
+    accumulation: Option<BlitTexture>,
+}
+
+/// A texture with the bind group reading it.
+struct BlitTexture {
+    size: [u32; 2],
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Blit {
+    /// `format` is the format of the textures `draw` and `draw_accumulation` will draw to.
+    pub fn new(
+        device: &wgpu::Device,
+        adapter: &wgpu::Adapter,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let features = adapter.get_texture_format_features(wgpu::TextureFormat::Rgba16Float);
+        let accumulation_format = if features
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+            && features.flags.contains(
+                wgpu::TextureFormatFeatureFlags::BLENDABLE
+                    | wgpu::TextureFormatFeatureFlags::FILTERABLE,
+            ) {
+            wgpu::TextureFormat::Rgba16Float
+        } else {
+            format
+        };
+
+        // linear so that previews are smooth
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Blit Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("blit_bind_group_layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blit Shader"),
+            source: wgpu::ShaderSource::Wgsl(BLIT_SOURCE.into()),
+        });
+        let create_pipeline = |label, format, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let pipeline = create_pipeline("Blit Pipeline", format, wgpu::BlendState::REPLACE);
+        // average = sample * constant + average * (1 - constant)
+        let average = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Constant,
+            dst_factor: wgpu::BlendFactor::OneMinusConstant,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let accumulate_pipeline = create_pipeline(
+            "Accumulate Pipeline",
+            accumulation_format,
+            wgpu::BlendState {
+                color: average,
+                alpha: average,
+            },
+        );
+
+        Self {
+            bind_group_layout,
+            sampler,
+            pipeline,
+            accumulate_pipeline,
+            format,
+            accumulation_format,
+            target: None,
+            accumulation: None,
+        }
+    }
+
+    /// Whether the average is kept in floating point, which many samples do not round away.
+    pub fn is_accumulation_float(&self) -> bool {
+        self.accumulation_format == wgpu::TextureFormat::Rgba16Float
+    }
+
+    /// View of the render target, resized to `size` if it is not already.
+    pub fn get_target(&mut self, device: &wgpu::Device, size: [u32; 2]) -> &wgpu::TextureView {
+        if self.target.as_ref().map(|target| target.size) != Some(size) {
+            self.target = Some(self.create_texture(device, "Blit Texture", self.format, size));
+        }
+        &self.target.as_ref().expect("target was just created").view
+    }
+
+    fn create_texture(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        format: wgpu::TextureFormat,
+        size: [u32; 2],
+    ) -> BlitTexture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size[0],
+                height: size[1],
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("blit_bind_group"),
+        });
+        BlitTexture {
+            size,
+            _texture: texture,
+            view,
+            bind_group,
+        }
+    }
+
+    /// Record averaging the render target, as last rendered to, in with `weight`, 1.0
+    /// starting a new average.
+    pub fn accumulate(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        weight: f32,
+    ) {
+        let size = self
+            .target
+            .as_ref()
+            .expect("get_target creates the target")
+            .size;
+        if self
+            .accumulation
+            .as_ref()
+            .map(|accumulation| accumulation.size)
+            != Some(size)
+        {
+            let format = self.accumulation_format;
+            self.accumulation =
+                Some(self.create_texture(device, "Accumulation Texture", format, size));
+        }
+        let (Some(target), Some(accumulation)) = (&self.target, &self.accumulation) else {
+            unreachable!("both textures were just created");
+        };
+        let weight = weight as f64;
+        let blend_constant = wgpu::Color {
+            r: weight,
+            g: weight,
+            b: weight,
+            a: weight,
+        };
+        Self::draw_texture(
+            encoder,
+            &self.accumulate_pipeline,
+            target,
+            &accumulation.view,
+            Some(blend_constant),
+        );
+    }
+
+    /// Record drawing the render target, as last rendered to, over all of `view`.
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let target = self.target.as_ref().expect("get_target creates the target");
+        Self::draw_texture(encoder, &self.pipeline, target, view, None);
+    }
+
+    /// Record drawing the average of the samples so far over all of `view`.
+    pub fn draw_accumulation(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let accumulation = self
+            .accumulation
+            .as_ref()
+            .expect("accumulate creates the accumulation");
+        Self::draw_texture(encoder, &self.pipeline, accumulation, view, None);
+    }
+
+    /// Draw `source` over `view` with `pipeline`, blending by `blend_constant` if given
+    /// and replacing what `view` holds otherwise.
+    fn draw_texture(
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: &BlitTexture,
+        view: &wgpu::TextureView,
+        blend_constant: Option<wgpu::Color>,
+    ) {
+        let load = match blend_constant {
+            Some(_) => wgpu::LoadOp::Load,
+            None => wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        if let Some(blend_constant) = blend_constant {
+            render_pass.set_blend_constant(blend_constant);
+        }
+        render_pass.set_bind_group(0, &source.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Times `IterationCache::compute` iterates over `frames` updates as `State::update`
+    /// makes them, with `palette_moves` telling the frames the palette moves on.
+    fn count_iterations(frames: u32, palette_moves: impl Fn(u32) -> bool) -> u32 {
+        let sampling = Sampling::default();
+        let mut uniform = Uniform::default();
+        let (mut refinement, mut previous) = (Refinement::Preview, None);
+        let (mut iterated, mut iterations) = (None, 0);
+        for frame in 0..frames {
+            if palette_moves(frame) {
+                uniform.palette_offset = (uniform.palette_offset + 0.01) % 1.0;
+            }
+            let key = get_refinement_key(&uniform);
+            refinement = refinement.advance(previous.as_ref(), &key, sampling.get_samples());
+            previous = Some(key);
+            uniform.sample_offset = refinement.get_sample_offset(&sampling, uniform.sample_offset);
+            // finished frames draw the average without rendering
+            if refinement != Refinement::Finished {
+                let iteration = (iteration_key(&uniform), refinement.get_size(640, 480));
+                if iterated != Some(iteration) {
+                    iterated = Some(iteration);
+                    iterations += 1;
+                }
+            }
+        }
+        iterations
+    }
+
+    #[test]
+    fn cycling_colours_cached_points() {
+        // the preview and the first sample, then only colouring
+        assert_eq!(count_iterations(100, |_| true), 2);
+        // the preview and every sample, then nothing new once the palette starts moving
+        let samples = Sampling::default().get_samples();
+        assert_eq!(count_iterations(50, |_| false), 1 + samples);
+        assert_eq!(count_iterations(100, |frame| frame >= 50), 1 + samples);
+        // a palette that stops starts sampling over, the first sample is cached already
+        assert_eq!(count_iterations(100, |frame| frame < 50), 2 + samples - 1);
+    }
+
+    #[test]
+    fn cycling_starts_over_when_the_palette_stops() {
+        let samples = 4;
+        let key = get_refinement_key(&Uniform::default());
+        let moved = Uniform {
+            palette_offset: 0.5,
+            ..key
+        };
+        let mut refinement = Refinement::Finished;
+        refinement = refinement.advance(Some(&key), &moved, samples);
+        assert_eq!(refinement, Refinement::Cycling);
+        refinement = refinement.advance(Some(&moved), &moved, samples);
+        assert_eq!(refinement, Refinement::Sample(0));
+        for _ in 1..samples {
+            refinement = refinement.advance(Some(&moved), &moved, samples);
+        }
+        assert_eq!(refinement, Refinement::Sample(samples - 1));
+        refinement = refinement.advance(Some(&moved), &moved, samples);
+        assert_eq!(refinement, Refinement::Finished);
+    }
+}
@@ -0,0 +1,404 @@
+use wgpu::util::DeviceExt;
+
+use crate::bigfloat::BigFloat;
+use crate::cache::IterationCache;
+use crate::expression::{Expression, ExpressionError};
+use crate::palette::{Palette, PaletteTexture};
+use crate::perturbation::{OrbitTexture, ReferenceOrbit};
+use crate::uniform::{Uniform, PRECISION_PERTURBATION};
+use crate::vertex::{Vertex, INDICES, VERTICES};
+use crate::viewport::Viewport;
+
+/// Request a device from the first adapter that can draw to `compatible_surface`,
+/// or any adapter when rendering offscreen. `None` if there is no usable adapter.
+pub async fn request_device(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+) -> Option<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface,
+            force_fallback_adapter: false,
+        })
+        .await?;
+
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                // WebGL doesn't support all of wgpu's features, so if
+                // we're building for the web we'll have to disable some.
+                limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    wgpu::Limits::default()
+                }
+                // allow textures as large as the adapter can, for big offscreen renders
+                .using_resolution(adapter.limits()),
+            },
+            None, // Trace path
+        )
+        .await
+        .ok()?;
+    Some((adapter, device, queue))
+}
+
+/// Shader source as written in `shader.wgsl`, which computes the default custom formula.
+const SHADER_SOURCE: &str = include_str!("shaders/shader.wgsl");
+
+/// Start of the function whose body `shader_source` replaces.
+const CUSTOM_FORMULA_SIGNATURE: &str =
+    "fn custom_formula(z: vec2<f32>, c: vec2<f32>) -> vec2<f32> {\n";
+
+/// The shader with `formula` compiled into `custom_formula`, checked with the same
+/// validation wgpu does so that errors come back here instead of in `create_shader_module`.
+pub fn shader_source(formula: &Expression) -> Result<String, ExpressionError> {
+    let start = SHADER_SOURCE
+        .find(CUSTOM_FORMULA_SIGNATURE)
+        .expect("shader defines custom_formula")
+        + CUSTOM_FORMULA_SIGNATURE.len();
+    let end = start
+        + SHADER_SOURCE[start..]
+            .find("\n}\n")
+            .expect("custom_formula ends");
+    let source = format!(
+        "{}    return {};{}",
+        &SHADER_SOURCE[..start],
+        formula.to_wgsl(),
+        &SHADER_SOURCE[end..]
+    );
+
+    let module = naga::front::wgsl::parse_str(&source)
+        .map_err(|error| ExpressionError::Shader(error.emit_to_string(&source)))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|error| ExpressionError::Shader(error.emit_to_string(&source)))?;
+    Ok(source)
+}
+
+/// Everything the reference orbit depends on.
+#[derive(Clone, PartialEq)]
+struct ReferenceParameters {
+    center: [BigFloat; 2],
+    c: [f64; 2],
+    fractal_kind: i32,
+    power: u32,
+    max_iterations: u32,
+    bits: u64,
+}
+
+/// The render pipeline and the GPU resources it reads, independent of where it draws to.
+///
+/// Used by `State` to draw to the window surface and by `HeadlessRenderer` to draw offscreen.
+pub struct Renderer {
+    render_pipeline: wgpu::RenderPipeline,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    /// Format of the textures drawn to, to rebuild the pipeline.
+    format: wgpu::TextureFormat,
+    /// Custom formula compiled into `render_pipeline`.
+    formula: Expression,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    orbit_texture: OrbitTexture,
+    reference_parameters: Option<ReferenceParameters>,
+    palette_texture: PaletteTexture,
+    /// Iterates only when needed and colours from the result, `None` where it is not supported,
+    /// which leaves `render_pipeline` to iterate on every frame.
+    cache: Option<IterationCache>,
+}
+
+impl Renderer {
+    /// `format` is the format of the textures `render` will draw to.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        palette: &Palette,
+    ) -> Self {
+        let uniform = Uniform::default();
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("uniform_bind_group_layout"),
+            });
+
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("uniform_bind_group"),
+        });
+
+        let orbit_texture = OrbitTexture::new(device, uniform.max_iterations + 1);
+
+        let palette_texture = PaletteTexture::new(device, queue, palette);
+
+        let bind_group_layouts = [
+            &uniform_bind_group_layout,
+            &orbit_texture.bind_group_layout,
+            &palette_texture.bind_group_layout,
+        ];
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+
+        let shader = create_shader(device, SHADER_SOURCE);
+        let render_pipeline =
+            create_render_pipeline(device, &render_pipeline_layout, format, &shader, "fs_main");
+        let cache = IterationCache::is_supported(device)
+            .then(|| IterationCache::new(device, &bind_group_layouts, format, SHADER_SOURCE));
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let num_indices = INDICES.len() as u32;
+
+        Self {
+            render_pipeline,
+            render_pipeline_layout,
+            format,
+            formula: Expression::default(),
+            vertex_buffer,
+            index_buffer,
+            num_indices,
+            uniform_buffer,
+            uniform_bind_group,
+            orbit_texture,
+            reference_parameters: None,
+            palette_texture,
+            cache,
+        }
+    }
+
+    pub fn set_palette(&self, queue: &wgpu::Queue, palette: &Palette) {
+        self.palette_texture.write(queue, palette);
+    }
+
+    /// Rebuild the pipeline with `formula` as the custom formula, if it is not already.
+    /// On error the previous formula stays.
+    pub fn set_formula(
+        &mut self,
+        device: &wgpu::Device,
+        formula: &Expression,
+    ) -> Result<(), ExpressionError> {
+        if self.formula == *formula {
+            return Ok(());
+        }
+        let source = shader_source(formula)?;
+        let shader = create_shader(device, &source);
+        self.render_pipeline = create_render_pipeline(
+            device,
+            &self.render_pipeline_layout,
+            self.format,
+            &shader,
+            "fs_main",
+        );
+        if let Some(cache) = &mut self.cache {
+            cache.set_source(device, &source);
+        }
+        self.formula = formula.clone();
+        Ok(())
+    }
+
+    /// Upload `uniform`, which must already hold `viewport`, along with the
+    /// reference orbit when the shader needs one (which also sets `reference_length`).
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        viewport: &Viewport,
+        uniform: &mut Uniform,
+    ) {
+        if uniform.precision_mode == PRECISION_PERTURBATION {
+            self.update_reference(device, queue, viewport, uniform);
+        }
+        if let Some(cache) = &mut self.cache {
+            cache.prepare(uniform);
+        }
+
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[*uniform]));
+    }
+
+    /// Recompute the reference orbit at the center of `viewport` if anything it depends on changed.
+    fn update_reference(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        viewport: &Viewport,
+        uniform: &mut Uniform,
+    ) {
+        let parameters = ReferenceParameters {
+            center: viewport.center.clone(),
+            c: uniform.get_c(),
+            fractal_kind: uniform.fractal_kind,
+            power: uniform.get_integer_power().unwrap_or(2),
+            max_iterations: uniform.max_iterations,
+            bits: viewport.get_precision(),
+        };
+        if self.reference_parameters.as_ref() == Some(&parameters) {
+            return;
+        }
+
+        let c = [
+            BigFloat::from_f64(parameters.c[0]),
+            BigFloat::from_f64(parameters.c[1]),
+        ];
+        let (power, max_iterations, bits) =
+            (parameters.power, parameters.max_iterations, parameters.bits);
+        let fractal_kind = uniform.get_fractal_kind();
+        let formula = fractal_kind.get_formula();
+        let orbit = if fractal_kind.is_julia() {
+            ReferenceOrbit::compute(formula, power, &parameters.center, &c, max_iterations, bits)
+        } else {
+            ReferenceOrbit::compute(formula, power, &c, &parameters.center, max_iterations, bits)
+        };
+        self.orbit_texture.write(device, queue, &orbit);
+        uniform.reference_length = orbit.len();
+        self.reference_parameters = Some(parameters);
+    }
+
+    /// Record drawing the fractal over all of `view`, which is `width` by `height`.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let bind_groups = [
+            &self.uniform_bind_group,
+            &self.orbit_texture.bind_group,
+            &self.palette_texture.bind_group,
+        ];
+        if let Some(cache) = &mut self.cache {
+            cache.compute(device, encoder, &bind_groups, width, height);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        match &self.cache {
+            Some(cache) => cache.set_color_pipeline(&mut render_pass, &bind_groups),
+            None => {
+                render_pass.set_pipeline(&self.render_pipeline);
+                for (index, bind_group) in bind_groups.into_iter().enumerate() {
+                    render_pass.set_bind_group(index as u32, bind_group, &[]);
+                }
+            }
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+}
+
+fn create_shader(device: &wgpu::Device, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shader"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    })
+}
+
+/// The pipeline drawing over the whole target with `fragment_entry_point` of `shader`.
+pub fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    shader: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent::REPLACE,
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
+            // or Features::POLYGON_MODE_POINT
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        // If the pipeline will be used with a multiview render pass, this
+        // indicates how many array layers the attachments will have.
+        multiview: None,
+    })
+}
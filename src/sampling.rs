@@ -0,0 +1,84 @@
+/// Most samples per side of a pixel, `--samples` beyond it would take minutes per frame.
+pub const SAMPLES_MAX: u32 = 16;
+
+/// Samples the window averages while accumulating, past which new samples barely move
+/// the half precision average.
+pub const ACCUMULATION_SAMPLES: u32 = 256;
+
+/// Where the samples of a pixel are taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SamplePattern {
+    /// At the centers of the cells of an N by N grid, the same as rendering N times larger
+    /// and averaging.
+    #[default]
+    Grid,
+    /// At random points within the cells of the grid, which trades the regular aliasing
+    /// patterns of fine detail for noise.
+    Jittered,
+}
+
+/// How many samples of every pixel are averaged and where, `count` by `count` of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sampling {
+    pub pattern: SamplePattern,
+    /// Samples per side of a pixel, from 1 to `SAMPLES_MAX`.
+    pub count: u32,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            pattern: SamplePattern::Grid,
+            count: 2,
+        }
+    }
+}
+
+impl Sampling {
+    /// Samples per pixel.
+    pub fn get_samples(&self) -> u32 {
+        self.count * self.count
+    }
+
+    /// Offset of sample `index` from the center of the pixel, in pixels from -0.5 to 0.5 with
+    /// y pointing down, as `Uniform::sample_offset` takes it. The jitter is the same on every
+    /// run, so that renders can be reproduced.
+    pub fn get_offset(&self, index: u32) -> [f32; 2] {
+        let cell = [index % self.count, index / self.count];
+        let within = match self.pattern {
+            SamplePattern::Grid => [0.5, 0.5],
+            SamplePattern::Jittered => [hash(2 * index), hash(2 * index + 1)],
+        };
+        [0, 1].map(|axis| (cell[axis] as f32 + within[axis]) / self.count as f32 - 0.5)
+    }
+
+    /// Offset of sample `index` of an average going on past the samples of the grid,
+    /// which continues at points spread evenly over the pixel by the Halton sequence.
+    pub fn get_accumulated_offset(&self, index: u32) -> [f32; 2] {
+        if index < self.get_samples() {
+            self.get_offset(index)
+        } else {
+            [halton(index + 1, 2) - 0.5, halton(index + 1, 3) - 0.5]
+        }
+    }
+}
+
+/// Element `index` of the Halton sequence of `base`, from 0.0 to 1.0.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let (mut value, mut fraction) = (0.0, 1.0);
+    while index > 0 {
+        fraction /= base as f32;
+        value += fraction * (index % base) as f32;
+        index /= base;
+    }
+    value
+}
+
+/// Pseudorandom number from 0.0 to 1.0 for `value`, a PCG hash.
+fn hash(value: u32) -> f32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    let word = (word >> 22) ^ word;
+    // the 24 high bits are exact in an f32 and keep the result below 1.0
+    (word >> 8) as f32 / (1 << 24) as f32
+}
@@ -0,0 +1,684 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bigfloat::BigFloat;
+use crate::expression::Expression;
+use crate::fractal::{Formula, FractalKind};
+use crate::palette::{Palette, PALETTE_WIDTH};
+use crate::polynomial::Polynomial;
+use crate::uniform::{
+    Uniform, COLORING_BANDED, COLORING_DISTANCE, COLORING_HISTOGRAM, COLORING_SMOOTH,
+    COLORING_STRIPE, COLORING_TRAP, COLORING_TRIANGLE, INTERIOR_BLACK, INTERIOR_FINAL_Z,
+    INTERIOR_MULTIPLIER, INTERIOR_PERIOD, MAX_ITERATIONS_LIMIT, MAX_ITERATIONS_MIN,
+    NEWTON_METHOD_HALLEY, NEWTON_METHOD_NEWTON, TRAP_CIRCLE, TRAP_CROSS, TRAP_LINE, TRAP_POINT,
+};
+use crate::viewport::Viewport;
+
+/// Version written to parameter files, files from newer versions are rejected. Version 3
+/// added the formula, the Newton polynomial, traps, interior colouring, the stripe density
+/// and histogram colouring, which older versions cannot read.
+pub const SCENE_FILE_VERSION: u32 = 3;
+
+/// Keyword of the PNG text chunk holding the parameter file of an exported image.
+pub const PNG_KEYWORD: &str = "fractal-explorer";
+
+/// Everything that decides what an image looks like, apart from its size in pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub viewport: Viewport,
+    /// The fields describing the view are filled in from `viewport` when rendering.
+    pub uniform: Uniform,
+    /// Formula of the custom fractal kinds, `uniform` holds what follows from it.
+    pub formula: Expression,
+    pub palette: Palette,
+    /// Set `uniform.max_iterations` from the zoom depth.
+    pub max_iterations_auto: bool,
+    /// The Julia set parameter as given, of which `uniform` only keeps about 48 bits,
+    /// see `get_c`.
+    pub c: [f64; 2],
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    PngEncoding(png::EncodingError),
+    PngDecoding(png::DecodingError),
+    /// The PNG was not written by the explorer, it has no parameter file.
+    NoParameters,
+    /// Written by a newer version of the program.
+    Version(u32),
+    Invalid(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "{}", error),
+            SceneError::Toml(error) => write!(f, "invalid parameter file: {}", error),
+            SceneError::PngEncoding(error) => write!(f, "{}", error),
+            SceneError::PngDecoding(error) => write!(f, "invalid PNG: {}", error),
+            SceneError::NoParameters => write!(f, "the image holds no view parameters"),
+            SceneError::Version(version) => write!(
+                f,
+                "parameter file version {} is newer than the supported version {}",
+                version, SCENE_FILE_VERSION
+            ),
+            SceneError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(error: std::io::Error) -> Self {
+        SceneError::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for SceneError {
+    fn from(error: toml::de::Error) -> Self {
+        SceneError::Toml(error)
+    }
+}
+
+impl From<png::EncodingError> for SceneError {
+    fn from(error: png::EncodingError) -> Self {
+        SceneError::PngEncoding(error)
+    }
+}
+
+impl From<png::DecodingError> for SceneError {
+    fn from(error: png::DecodingError) -> Self {
+        SceneError::PngDecoding(error)
+    }
+}
+
+/// Parameter file, TOML with the view in its own table:
+///
+/// ```toml
+/// version = 3
+/// fractal = "mandelbrot"
+/// c = [0.0, 0.0]
+/// power = [2.0, 0.0]
+/// max_iterations = 256
+/// max_iterations_auto = true
+/// coloring = "smooth"
+///
+/// [view]
+/// center = ["-0.75", "0.1"]
+/// width = 0.001
+/// rotation = 0.0
+///
+/// [palette]
+/// name = "Ultra"
+/// period = 64.0
+/// offset = 0.0
+/// ```
+///
+/// Custom formulas are written as typed, `formula = "z^3 + c*sin(z)"` after the power, and
+/// interior colouring other than black as `interior = "period"`, `"multiplier"` or `"final-z"`
+/// after the colouring. Stripe average colouring writes its `stripe_density` there as well.
+/// The Newton and Nova fractals add their own table, with the polynomial as its
+/// coefficients from the highest power of `z` down:
+///
+/// ```toml
+/// [newton]
+/// polynomial = [[1.0, 0.0], [0.0, 0.0], [0.0, 0.0], [-1.0, 0.0]]
+/// relaxation = [1.0, 0.0]
+/// method = "newton"
+/// ```
+///
+/// Orbit trap colouring adds the trap, with the radius only used by circles:
+///
+/// ```toml
+/// [trap]
+/// shape = "circle"
+/// position = [0.0, 0.0]
+/// radius = 0.5
+/// ```
+///
+/// The center is written as exact decimals and every float in its shortest form that
+/// reads back the same, so saving what was loaded gives the same file. Palettes not
+/// in `Palette::library` are written out colour by colour. Version 1 files have no power,
+/// it was always 2, and versions before 3 none of the optional fields above.
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    version: u32,
+    fractal: FractalKind,
+    c: [f64; 2],
+    #[serde(default = "default_power")]
+    power: [f64; 2],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    formula: Option<String>,
+    max_iterations: u32,
+    max_iterations_auto: bool,
+    coloring: ColoringFile,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interior: Option<InteriorFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stripe_density: Option<f64>,
+    view: ViewFile,
+    palette: PaletteFile,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    newton: Option<NewtonFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trap: Option<TrapFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ColoringFile {
+    Banded,
+    Smooth,
+    Distance,
+    Trap,
+    Stripe,
+    Triangle,
+    Histogram,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum InteriorFile {
+    Period,
+    Multiplier,
+    FinalZ,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ViewFile {
+    center: [String; 2],
+    width: f64,
+    /// Radians.
+    rotation: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PaletteFile {
+    name: String,
+    period: f64,
+    offset: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    colors: Option<Vec<[f64; 3]>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NewtonFile {
+    polynomial: Vec<[f64; 2]>,
+    relaxation: [f64; 2],
+    method: NewtonMethodFile,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NewtonMethodFile {
+    Newton,
+    Halley,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrapFile {
+    shape: TrapShapeFile,
+    position: [f64; 2],
+    radius: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TrapShapeFile {
+    Point,
+    Line,
+    Cross,
+    Circle,
+}
+
+fn default_power() -> [f64; 2] {
+    Uniform::default().power.map(to_file_float)
+}
+
+/// The `f64` with the shortest decimal form that reads back as `value`, floats are written
+/// as `f64` and would otherwise show every digit of the `f32`.
+fn to_file_float(value: f32) -> f64 {
+    value
+        .to_string()
+        .parse()
+        .expect("f32 is written as a valid f64")
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            viewport: Viewport::default(),
+            uniform: Uniform::default(),
+            formula: Expression::default(),
+            palette: Palette::library().remove(0),
+            max_iterations_auto: true,
+            c: Uniform::default().get_c(),
+        }
+    }
+}
+
+impl Scene {
+    /// The Julia set parameter, exactly as last set if `uniform` still holds it and as
+    /// `uniform` holds it otherwise, such as after it followed the mouse.
+    pub fn get_c(&self) -> [f64; 2] {
+        let mut uniform = self.uniform;
+        uniform.set_c(self.c);
+        if (uniform.c, uniform.c_lo) == (self.uniform.c, self.uniform.c_lo) {
+            self.c
+        } else {
+            self.uniform.get_c()
+        }
+    }
+
+    pub fn set_c(&mut self, c: [f64; 2]) {
+        self.c = c;
+        self.uniform.set_c(c);
+    }
+
+    /// `uniform` with the iteration count set when it is automatic.
+    pub fn get_uniform(&self) -> Uniform {
+        let mut uniform = self.uniform;
+        if self.max_iterations_auto {
+            uniform.max_iterations = self.viewport.get_max_iterations_auto();
+        }
+        uniform
+    }
+
+    /// Load a parameter file, or the parameters of a PNG written by `save_png`.
+    pub fn load(path: &Path) -> Result<Self, SceneError> {
+        let is_png = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+        if is_png {
+            return Self::load_png(path);
+        }
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SceneError> {
+        std::fs::write(path, self.to_toml())?;
+        Ok(())
+    }
+
+    /// Write `image` as a PNG file, with the parameter file of the scene in a text chunk.
+    pub fn save_png(&self, path: &Path, image: &image::RgbaImage) -> Result<(), SceneError> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk("Software".to_string(), "Fractal Explorer".to_string())?;
+        encoder.add_itxt_chunk(PNG_KEYWORD.to_string(), self.to_toml())?;
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(image.as_raw())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Read the parameters of a PNG written by `save_png`, the pixels are not decoded.
+    pub fn load_png(path: &Path) -> Result<Self, SceneError> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let reader = png::Decoder::new(file).read_info()?;
+        let chunk = reader
+            .info()
+            .utf8_text
+            .iter()
+            .find(|chunk| chunk.keyword == PNG_KEYWORD)
+            .ok_or(SceneError::NoParameters)?;
+        Self::from_toml(&chunk.get_text()?)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, SceneError> {
+        // check the version first, newer files may not parse as this version
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let version = toml::from_str::<Version>(contents)?.version;
+        if version > SCENE_FILE_VERSION {
+            return Err(SceneError::Version(version));
+        }
+        let file: SceneFile = toml::from_str(contents)?;
+
+        let parse_center = |value: &str| {
+            value
+                .parse::<BigFloat>()
+                .map_err(|error| SceneError::Invalid(format!("center {}: {}", value, error)))
+        };
+        if !(file.view.width > 0.0 && file.view.width.is_finite()) {
+            return Err(SceneError::Invalid(format!(
+                "view width must be positive, got {}",
+                file.view.width
+            )));
+        }
+        if !(MAX_ITERATIONS_MIN..=MAX_ITERATIONS_LIMIT).contains(&file.max_iterations) {
+            return Err(SceneError::Invalid(format!(
+                "max_iterations must be between {} and {}, got {}",
+                MAX_ITERATIONS_MIN, MAX_ITERATIONS_LIMIT, file.max_iterations
+            )));
+        }
+        let power = file.power.map(|part| part as f32);
+        if !power.iter().all(|part| part.is_finite()) {
+            return Err(SceneError::Invalid(format!(
+                "power must be finite, got {:?}",
+                file.power
+            )));
+        }
+        let palette_period = file.palette.period as f32;
+        if !(palette_period > 0.0 && palette_period.is_finite()) {
+            return Err(SceneError::Invalid(format!(
+                "palette period must be positive, got {}",
+                file.palette.period
+            )));
+        }
+        let palette_offset = file.palette.offset as f32;
+        if !palette_offset.is_finite() {
+            return Err(SceneError::Invalid(format!(
+                "palette offset must be finite, got {}",
+                file.palette.offset
+            )));
+        }
+        let viewport = Viewport {
+            center: [
+                parse_center(&file.view.center[0])?,
+                parse_center(&file.view.center[1])?,
+            ],
+            scale: file.view.width,
+            rotation: file.view.rotation,
+            ..Viewport::default()
+        };
+
+        let mut uniform = Uniform {
+            fractal_kind: file.fractal as i32,
+            power,
+            max_iterations: file.max_iterations,
+            coloring_mode: match file.coloring {
+                ColoringFile::Banded => COLORING_BANDED,
+                ColoringFile::Smooth => COLORING_SMOOTH,
+                ColoringFile::Distance => COLORING_DISTANCE,
+                ColoringFile::Trap => COLORING_TRAP,
+                ColoringFile::Stripe => COLORING_STRIPE,
+                ColoringFile::Triangle => COLORING_TRIANGLE,
+                ColoringFile::Histogram => COLORING_HISTOGRAM,
+            },
+            interior_mode: match file.interior {
+                None => INTERIOR_BLACK,
+                Some(InteriorFile::Period) => INTERIOR_PERIOD,
+                Some(InteriorFile::Multiplier) => INTERIOR_MULTIPLIER,
+                Some(InteriorFile::FinalZ) => INTERIOR_FINAL_Z,
+            },
+            palette_period,
+            palette_offset,
+            ..Uniform::default()
+        };
+        uniform.set_c(file.c);
+        let formula = match file.formula {
+            Some(formula) => formula
+                .parse::<Expression>()
+                .map_err(|error| SceneError::Invalid(format!("formula: {}", error)))?,
+            None => Expression::default(),
+        };
+        uniform.set_formula(&formula);
+        if let Some(newton) = file.newton {
+            let polynomial = Polynomial::new(&newton.polynomial)
+                .map_err(|error| SceneError::Invalid(error.to_string()))?;
+            let relaxation = newton.relaxation.map(|part| part as f32);
+            if !relaxation.iter().all(|part| part.is_finite()) {
+                return Err(SceneError::Invalid(format!(
+                    "relaxation must be finite, got {:?}",
+                    newton.relaxation
+                )));
+            }
+            uniform.set_polynomial(&polynomial);
+            uniform.relaxation = relaxation;
+            uniform.newton_method = match newton.method {
+                NewtonMethodFile::Newton => NEWTON_METHOD_NEWTON,
+                NewtonMethodFile::Halley => NEWTON_METHOD_HALLEY,
+            };
+        }
+        if let Some(stripe_density) = file.stripe_density {
+            let density = stripe_density as f32;
+            if !(density > 0.0 && density.is_finite()) {
+                return Err(SceneError::Invalid(format!(
+                    "stripe density must be positive, got {}",
+                    stripe_density
+                )));
+            }
+            uniform.stripe_density = density;
+        }
+        if let Some(trap) = file.trap {
+            let position = trap.position.map(|part| part as f32);
+            let radius = trap.radius as f32;
+            if !(position.iter().all(|part| part.is_finite()) && radius.is_finite()) {
+                return Err(SceneError::Invalid(format!(
+                    "trap position and radius must be finite, got {:?} and {}",
+                    trap.position, trap.radius
+                )));
+            }
+            uniform.trap_shape = match trap.shape {
+                TrapShapeFile::Point => TRAP_POINT,
+                TrapShapeFile::Line => TRAP_LINE,
+                TrapShapeFile::Cross => TRAP_CROSS,
+                TrapShapeFile::Circle => TRAP_CIRCLE,
+            };
+            uniform.trap_position = position;
+            uniform.trap_radius = radius;
+        }
+
+        let palette = match file.palette.colors {
+            Some(colors) if colors.len() == PALETTE_WIDTH as usize => Palette {
+                name: file.palette.name,
+                colors: colors
+                    .into_iter()
+                    .map(|color| color.map(|part| part as f32))
+                    .collect(),
+            },
+            Some(colors) => {
+                return Err(SceneError::Invalid(format!(
+                    "palette must have {} colors, got {}",
+                    PALETTE_WIDTH,
+                    colors.len()
+                )))
+            }
+            None => Palette::library()
+                .into_iter()
+                .find(|palette| palette.name == file.palette.name)
+                .ok_or_else(|| {
+                    SceneError::Invalid(format!(
+                        "unknown palette '{}' without colors",
+                        file.palette.name
+                    ))
+                })?,
+        };
+
+        Ok(Self {
+            viewport,
+            uniform,
+            formula,
+            palette,
+            max_iterations_auto: file.max_iterations_auto,
+            c: file.c,
+        })
+    }
+
+    pub fn to_toml(&self) -> String {
+        let builtin = Palette::library().contains(&self.palette);
+        let formula = self.uniform.get_fractal_kind().get_formula();
+        let newton = formula == Formula::Newton;
+        let file = SceneFile {
+            version: SCENE_FILE_VERSION,
+            fractal: self.uniform.get_fractal_kind(),
+            c: self.get_c(),
+            power: self.uniform.power.map(to_file_float),
+            formula: (formula == Formula::Custom).then(|| self.formula.to_string()),
+            max_iterations: self.uniform.max_iterations,
+            max_iterations_auto: self.max_iterations_auto,
+            coloring: match self.uniform.coloring_mode {
+                COLORING_BANDED => ColoringFile::Banded,
+                COLORING_DISTANCE => ColoringFile::Distance,
+                COLORING_TRAP => ColoringFile::Trap,
+                COLORING_STRIPE => ColoringFile::Stripe,
+                COLORING_TRIANGLE => ColoringFile::Triangle,
+                COLORING_HISTOGRAM => ColoringFile::Histogram,
+                _ => ColoringFile::Smooth,
+            },
+            interior: match self.uniform.interior_mode {
+                INTERIOR_PERIOD => Some(InteriorFile::Period),
+                INTERIOR_MULTIPLIER => Some(InteriorFile::Multiplier),
+                INTERIOR_FINAL_Z => Some(InteriorFile::FinalZ),
+                _ => None,
+            },
+            stripe_density: (self.uniform.coloring_mode == COLORING_STRIPE)
+                .then(|| to_file_float(self.uniform.stripe_density)),
+            view: ViewFile {
+                center: self.viewport.center.clone().map(|value| value.to_string()),
+                width: self.viewport.scale,
+                rotation: self.viewport.rotation,
+            },
+            palette: PaletteFile {
+                name: self.palette.name.clone(),
+                period: to_file_float(self.uniform.palette_period),
+                offset: to_file_float(self.uniform.palette_offset),
+                colors: (!builtin).then(|| {
+                    let colors = self.palette.colors.iter();
+                    colors.map(|color| color.map(to_file_float)).collect()
+                }),
+            },
+            newton: newton.then(|| {
+                let polynomial = self.uniform.get_polynomial();
+                let coefficients = polynomial.get_coefficients().iter();
+                NewtonFile {
+                    polynomial: coefficients
+                        .map(|coefficient| coefficient.map(|part| to_file_float(part as f32)))
+                        .collect(),
+                    relaxation: self.uniform.relaxation.map(to_file_float),
+                    method: if self.uniform.newton_method == NEWTON_METHOD_HALLEY {
+                        NewtonMethodFile::Halley
+                    } else {
+                        NewtonMethodFile::Newton
+                    },
+                }
+            }),
+            trap: (self.uniform.coloring_mode == COLORING_TRAP).then(|| TrapFile {
+                shape: match self.uniform.trap_shape {
+                    TRAP_LINE => TrapShapeFile::Line,
+                    TRAP_CROSS => TrapShapeFile::Cross,
+                    TRAP_CIRCLE => TrapShapeFile::Circle,
+                    _ => TrapShapeFile::Point,
+                },
+                position: self.uniform.trap_position.map(to_file_float),
+                radius: to_file_float(self.uniform.trap_radius),
+            }),
+        };
+        toml::to_string(&file).expect("parameter file is always valid TOML")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scene using the optional parts of the file.
+    fn get_scene() -> Scene {
+        let mut scene = Scene::default();
+        scene.viewport.center = [
+            "-0.7436438870371587".parse().unwrap(),
+            "0.1318259042053119".parse().unwrap(),
+        ];
+        scene.viewport.scale = 1.5e-9;
+        scene.viewport.rotation = 0.25;
+        scene.formula = "z^3 + c*sin(z)".parse().unwrap();
+        scene.uniform.set_fractal_kind(FractalKind::Custom);
+        scene.uniform.set_formula(&scene.formula);
+        scene.uniform.coloring_mode = COLORING_TRAP;
+        scene.uniform.trap_shape = TRAP_CIRCLE;
+        scene.uniform.trap_position = [0.5, -0.25];
+        scene.uniform.trap_radius = 0.1;
+        scene.uniform.interior_mode = INTERIOR_PERIOD;
+        scene.uniform.palette_period = 32.5;
+        scene.palette = Palette::from_stops("Gray", &[(0.0, [0.0; 3]), (0.5, [1.0; 3])]);
+        scene.max_iterations_auto = false;
+        scene.uniform.max_iterations = 1000;
+        scene
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        for scene in [Scene::default(), get_scene()] {
+            let contents = scene.to_toml();
+            let loaded = Scene::from_toml(&contents).unwrap();
+            assert_eq!(loaded, scene);
+            assert_eq!(loaded.to_toml(), contents);
+        }
+    }
+
+    #[test]
+    fn keeps_c_exactly() {
+        let c = [-0.8 + 3.0 * f64::EPSILON, 0.156 - 5.0 * f64::EPSILON];
+        let mut scene = Scene::default();
+        scene.set_c(c);
+        // more bits than the uniform holds
+        assert_ne!(scene.uniform.get_c(), c);
+        let loaded = Scene::from_toml(&scene.to_toml()).unwrap();
+        assert_eq!(loaded.get_c(), c);
+        assert_eq!(loaded, scene);
+        // a uniform that moved on is written as it is
+        scene.uniform.set_c([0.25, 0.5]);
+        assert_eq!(scene.get_c(), [0.25, 0.5]);
+    }
+
+    #[test]
+    fn reads_older_versions() {
+        let contents = Scene::default().to_toml();
+        let current = format!("version = {}", SCENE_FILE_VERSION);
+        assert!(contents.contains(&current));
+        let version_2 = contents.replace(&current, "version = 2");
+        assert_eq!(Scene::from_toml(&version_2).unwrap(), Scene::default());
+        let version_1 = contents
+            .replace(&current, "version = 1")
+            .replace("power = [2.0, 0.0]\n", "");
+        assert!(!version_1.contains("power"));
+        assert_eq!(Scene::from_toml(&version_1).unwrap(), Scene::default());
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let version = SCENE_FILE_VERSION + 1;
+        // newer files may have fields this version cannot read
+        let contents = format!("version = {}\nfractal = \"hyperbolic\"\n", version);
+        assert!(matches!(
+            Scene::from_toml(&contents),
+            Err(SceneError::Version(v)) if v == version
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let contents = Scene::default().to_toml();
+        let invalid = |from: &str, to: &str| {
+            assert!(contents.contains(from), "{}", from);
+            let result = Scene::from_toml(&contents.replacen(from, to, 1));
+            assert!(
+                matches!(result, Err(SceneError::Invalid(_))),
+                "{}: {:?}",
+                to,
+                result
+            );
+        };
+        invalid("width = ", "width = -");
+        invalid("name = \"Ultra\"", "name = \"Unknown\"");
+        for period in ["0.0", "-1.0", "nan", "inf", "1e40"] {
+            invalid("period = ", &format!("period = {} # ", period));
+        }
+        invalid("offset = ", "offset = nan # ");
+        invalid("center = [\"", "center = [\"x");
+        assert!(matches!(
+            Scene::from_toml("version = 2\nfractal = 3"),
+            Err(SceneError::Toml(_))
+        ));
+    }
+}
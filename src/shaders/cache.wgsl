@@ -0,0 +1,41 @@
+// Iteration cache, appended to shader.wgsl along with histogram.wgsl when compute shaders
+// are available. cs_iterate runs the escape loop of every pixel into the point textures
+// only when what it depends on changed, fs_color colours them on every frame
+
+// Point.escape and Point.rest of every pixel, as from get_point
+@group(3) @binding(0)
+var point_escape_storage: texture_storage_2d<rgba32float, write>;
+@group(3) @binding(1)
+var point_rest_storage: texture_storage_2d<rgba32float, write>;
+@group(3) @binding(2)
+var point_escape_texture: texture_2d<f32>;
+@group(3) @binding(3)
+var point_rest_texture: texture_2d<f32>;
+
+@compute @workgroup_size(8, 8)
+fn cs_iterate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(point_escape_storage);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+    // same as position_xy at the sample of the pixel
+    let position_xy = vec2<f32>(
+        (f32(id.x) + 0.5 + my_uniform.sample_offset.x) / f32(size.x) * 2.0 - 1.0,
+        1.0 - (f32(id.y) + 0.5 + my_uniform.sample_offset.y) / f32(size.y) * 2.0,
+    );
+    pixel_size = my_uniform.size.x / f32(size.x);
+    let point = get_point(get_offset(position_xy));
+    textureStore(point_escape_storage, vec2<i32>(id.xy), point.escape);
+    textureStore(point_rest_storage, vec2<i32>(id.xy), point.rest);
+}
+
+@fragment
+fn fs_color(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let point = Point(textureLoad(point_escape_texture, pixel, 0), textureLoad(point_rest_texture, pixel, 0));
+    let iterations = get_point_iterations(point);
+    if (my_uniform.coloring_mode == COLORING_HISTOGRAM && iterations >= 0.0) {
+        return vec4<f32>(get_histogram_color(iterations), 1.0);
+    }
+    return vec4<f32>(get_point_color(point), 1.0);
+}
@@ -0,0 +1,101 @@
+// Histogram equalized colouring, appended to shader.wgsl along with cache.wgsl.
+// After cs_iterate, cs_range finds the fewest and most iterations of the escaped pixels,
+// cs_bins counts them into bins spread evenly in between and cs_cumulate sums the bins up,
+// so that fs_color can colour each by the share of them that escaped sooner
+
+// Must match HISTOGRAM_BINS in histogram.rs
+const HISTOGRAM_BINS: u32 = 1024u;
+
+// Iterations are positive, so their bits compare like the floats do,
+// low is stored complemented to start from the zeroed buffer as well
+struct Histogram {
+    low_complement: atomic<u32>,
+    high: atomic<u32>,
+    bins: array<atomic<u32>, HISTOGRAM_BINS>,
+}
+
+// Same as Histogram, read back after cs_cumulate where bins holds the running total
+struct HistogramCounts {
+    low_complement: u32,
+    high: u32,
+    bins: array<u32, HISTOGRAM_BINS>,
+}
+
+@group(3) @binding(4)
+var<storage, read_write> histogram: Histogram;
+@group(3) @binding(5)
+var<storage, read> histogram_counts: HistogramCounts;
+
+// Iterations a point took to escape for COLORING_HISTOGRAM, -1.0 if it did not
+fn get_point_iterations(point: Point) -> f32 {
+    if (my_uniform.fractal_kind / 2 == FORMULA_NEWTON || point.escape.x >= 1.0) {
+        return -1.0;
+    }
+    return point.escape.x * f32(my_uniform.max_iterations);
+}
+
+// Iterations of the escaped pixel id as from get_point_iterations, -1.0 outside the texture
+fn load_iterations(id: vec3<u32>) -> f32 {
+    let size = textureDimensions(point_escape_texture);
+    if (id.x >= size.x || id.y >= size.y) {
+        return -1.0;
+    }
+    let escape = textureLoad(point_escape_texture, vec2<i32>(id.xy), 0);
+    return get_point_iterations(Point(escape, vec4<f32>(0.0)));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_range(@builtin(global_invocation_id) id: vec3<u32>) {
+    let iterations = load_iterations(id);
+    if (iterations >= 0.0) {
+        atomicMax(&histogram.low_complement, ~bitcast<u32>(iterations));
+        atomicMax(&histogram.high, bitcast<u32>(iterations));
+    }
+}
+
+// Bin of iterations and how far into it they are, from 0.0 to 1.0
+fn get_bin(iterations: f32, low: f32, high: f32) -> vec2<f32> {
+    let position = clamp((iterations - low) / max(high - low, 1e-30), 0.0, 1.0) * f32(HISTOGRAM_BINS);
+    let bin = min(floor(position), f32(HISTOGRAM_BINS - 1u));
+    return vec2<f32>(bin, position - bin);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_bins(@builtin(global_invocation_id) id: vec3<u32>) {
+    let iterations = load_iterations(id);
+    if (iterations < 0.0) {
+        return;
+    }
+    let low = bitcast<f32>(~atomicLoad(&histogram.low_complement));
+    let high = bitcast<f32>(atomicLoad(&histogram.high));
+    let bin = u32(get_bin(iterations, low, high).x);
+    atomicAdd(&histogram.bins[bin], 1u);
+}
+
+// A single invocation, the bins are few next to the pixels
+@compute @workgroup_size(1)
+fn cs_cumulate() {
+    var total = 0u;
+    for (var bin = 0u; bin < HISTOGRAM_BINS; bin++) {
+        total += atomicLoad(&histogram.bins[bin]);
+        atomicStore(&histogram.bins[bin], total);
+    }
+}
+
+// Palette colour at the share of escaped points that took fewer iterations,
+// interpolated within the bin so that the colours stay continuous
+fn get_histogram_color(iterations: f32) -> vec3<f32> {
+    let low = bitcast<f32>(~histogram_counts.low_complement);
+    let high = bitcast<f32>(histogram_counts.high);
+    let bin = get_bin(iterations, low, high);
+    let index = u32(bin.x);
+    var below = 0u;
+    if (index > 0u) {
+        below = histogram_counts.bins[index - 1u];
+    }
+    let count = histogram_counts.bins[index] - below;
+    let total = histogram_counts.bins[HISTOGRAM_BINS - 1u];
+    let share = (f32(below) + f32(count) * bin.y) / f32(total);
+    let position = fract(share + my_uniform.palette_offset);
+    return textureSampleLevel(palette_texture, palette_sampler, vec2<f32>(position, 0.5), 0.0).rgb;
+}
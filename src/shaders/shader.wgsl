@@ -1,15 +1,112 @@
 
 struct Uniform {
-   domain: vec4<f32>,
+   size: vec2<f32>,
+   rotation: vec2<f32>,
    mouse: vec2<f32>,
    c: vec2<f32>,
+   c_lo: vec2<f32>,
+   center: vec2<f32>,
+   center_lo: vec2<f32>,
+   power: vec2<f32>,
+   relaxation: vec2<f32>,
+   trap_position: vec2<f32>,
+   sample_offset: vec2<f32>,
    time: f32,
-   mandelbrot: i32,
-   padding: vec2<f32>,
+   fractal_kind: i32,
+   exponent: i32,
+   precision_mode: i32,
+   reference_length: u32,
+   one: f32,
+   max_iterations: u32,
+   coloring_mode: i32,
+   palette_period: f32,
+   palette_offset: f32,
+   degree: u32,
+   newton_method: i32,
+   formula_degree: f32,
+   trap_shape: i32,
+   trap_radius: f32,
+   interior_mode: i32,
+   stripe_density: f32,
+   // coefficient of z^k in xy and the k-th root in zw, 16 byte aligned after 4 bytes of padding
+   polynomial: array<vec4<f32>, 9>,
 }
 @group(0) @binding(0)
 var<uniform> my_uniform: Uniform;
 
+@group(1) @binding(0)
+var orbit_texture: texture_2d<f32>;
+
+@group(2) @binding(0)
+var palette_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var palette_sampler: sampler;
+
+const PRECISION_SINGLE: i32 = 0;
+const PRECISION_DOUBLE_SINGLE: i32 = 1;
+const PRECISION_PERTURBATION: i32 = 2;
+
+// fractal_kind / 2, fractal_kind % 2 is 0 for Julia sets and 1 for parameter planes
+// (must match Formula and FractalKind in fractal.rs)
+const FORMULA_MANDELBROT: i32 = 0;
+const FORMULA_BURNING_SHIP: i32 = 1;
+const FORMULA_TRICORN: i32 = 2;
+const FORMULA_CELTIC: i32 = 3;
+const FORMULA_PERPENDICULAR: i32 = 4;
+const FORMULA_BUFFALO: i32 = 5;
+const FORMULA_NEWTON: i32 = 6;
+const FORMULA_CUSTOM: i32 = 7;
+
+// Largest power iterated by complex multiplication, others go through the polar form
+// (must match POWER_INTEGER_MAX in uniform.rs)
+const POWER_INTEGER_MAX: i32 = 16;
+
+const NEWTON_METHOD_NEWTON: i32 = 0;
+const NEWTON_METHOD_HALLEY: i32 = 1;
+
+// The Newton iteration has converged once |z_next - z|^2 is below this
+const NEWTON_TOLERANCE_SQUARED: f32 = 1e-8;
+// Iterations after which the colour of a basin has faded to 1/e
+const NEWTON_SHADING: f32 = 16.0;
+
+const COLORING_BANDED: i32 = 0;
+const COLORING_SMOOTH: i32 = 1;
+const COLORING_DISTANCE: i32 = 2;
+const COLORING_TRAP: i32 = 3;
+const COLORING_STRIPE: i32 = 4;
+const COLORING_TRIANGLE: i32 = 5;
+const COLORING_HISTOGRAM: i32 = 6;
+
+const TRAP_POINT: i32 = 0;
+const TRAP_LINE: i32 = 1;
+const TRAP_CROSS: i32 = 2;
+const TRAP_CIRCLE: i32 = 3;
+
+// Distance to the orbit trap of points whose orbit escapes before it is measured
+const TRAP_DISTANCE_MAX: f32 = 1e30;
+// The palette repeats each time the distance to the orbit trap shrinks by 2^TRAP_PALETTE_PERIOD
+const TRAP_PALETTE_PERIOD: f32 = 4.0;
+
+const INTERIOR_BLACK: i32 = 0;
+const INTERIOR_PERIOD: i32 = 1;
+const INTERIOR_MULTIPLIER: i32 = 2;
+const INTERIOR_FINAL_Z: i32 = 3;
+
+// An orbit that comes back this close to an earlier point of itself is taken to be periodic,
+// or as close as two pixels when they are closer
+const PERIOD_TOLERANCE_SQUARED: f32 = 1e-12;
+// Step through the palette from one period to the next, irrational so that no two repeat
+const INTERIOR_PERIOD_STEP: f32 = 0.618034;
+// How much farther than the periodicity check allows an orbit may come back to z after
+// a whole period, as it is still converging
+const PERIOD_RETURN_FACTOR: f32 = 1e4;
+const TAU: f32 = 6.283185307;
+
+// Escape radius 2 is enough to tell escaping points, smooth colouring needs a larger one
+// (must match BAILOUT_SQUARED in perturbation.rs)
+const BAILOUT_SQUARED_BANDED: f32 = 4.0;
+const BAILOUT_SQUARED_SMOOTH: f32 = 65536.0;
+
 // Vertex shader
 
 struct VertexInput {
@@ -33,76 +130,954 @@ fn vs_main(
 
 // Fragment shader
 
-fn hsv2rgb(hue: f32, saturation: f32, value: f32) -> vec3<f32> {
-    // hue, saturation and value must be between 0.0 and 1.0
-    // formula adapted from https://www.tlbx.app/color-converter
-    let h = hue * 6.0;
-    let chroma = saturation * value;
-    let x = chroma * (1.0 - abs(h % 2.0 - 1.0));
-    let m = value - chroma;
-    if (h <= 1.0) {
-        return vec3<f32>(chroma + m, x + m, m);
-    } else if (h <= 2.0) {
-        return vec3<f32>(x + m, chroma + m, m);
-    } else if (h <= 3.0) {
-        return vec3<f32>(m, chroma + m, x + m);
-    } else if (h <= 4.0) {
-        return vec3<f32>(m, x + m, chroma + m);
-    } else if (h <= 5.0) {
-        return vec3<f32>(x + m, m, chroma + m);
+// Size of a pixel in units of 2^exponent, set by fs_main and cs_iterate before calling get_point
+var<private> pixel_size: f32;
+
+// How a point escaped: the fraction of iterations_max it took, as from get_fraction,
+// its distance to the set in pixels, 1.0 unless coloured by distance, the closest
+// its orbit came to the orbit trap, the palette position and brightness of
+// the interior colour, as from get_interior, if it did not escape, and the stripe
+// or triangle inequality average, as from get_average
+struct Escape {
+    fraction: f32,
+    distance: f32,
+    trap: f32,
+    interior: vec2<f32>,
+    average: f32,
+}
+
+// Running sum of the terms of the stripe or triangle inequality average along an orbit,
+// with the last term to take it back by one iteration
+struct Average {
+    sum: f32,
+    last: f32,
+    count: f32,
+}
+
+// Colour of a point from the fraction of iterations_max it took to escape, black if it did not
+fn get_color(fraction: f32) -> vec3<f32> {
+    if (fraction >= 1.0) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+
+    // the palette repeats every palette_period iterations
+    let iterations = fraction * f32(my_uniform.max_iterations);
+    let position = fract(iterations / my_uniform.palette_period + my_uniform.palette_offset);
+    // explicit level, sampling happens in non-uniform control flow
+    return textureSampleLevel(palette_texture, palette_sampler, vec2<f32>(position, 0.5), 0.0).rgb;
+}
+
+fn get_bailout_squared() -> f32 {
+    if (my_uniform.coloring_mode == COLORING_BANDED) {
+        return BAILOUT_SQUARED_BANDED;
+    }
+    return BAILOUT_SQUARED_SMOOTH;
+}
+
+// Fraction of iterations_max it took to escape, 1.0 if it did not,
+// i is the iteration at which |z|^2 first went above the bailout
+fn get_fraction(i: i32, z_norm_squared: f32) -> f32 {
+    let iterations_max: i32 = i32(my_uniform.max_iterations);
+    if (i >= iterations_max) {
+        return 1.0;
+    }
+    if (my_uniform.coloring_mode == COLORING_BANDED) {
+        return f32(i) / f32(iterations_max);
+    }
+
+    let iterations = max(f32(i) - get_iteration_excess(z_norm_squared), 0.0);
+    return iterations / f32(iterations_max);
+}
+
+// Part of the last iteration that went beyond the bailout, for the normalized iteration count.
+// log|z| roughly gets multiplied by the real part d of the power on every iteration after
+// the bailout, so subtracting log_d(log|z| / log(bailout)) from i makes the count continuous
+fn get_iteration_excess(z_norm_squared: f32) -> f32 {
+    var degree = my_uniform.power.x;
+    if (my_uniform.fractal_kind / 2 == FORMULA_CUSTOM) {
+        degree = my_uniform.formula_degree;
+    }
+    if (degree <= 1.0) {
+        return 0.0;
+    }
+    let ratio = log(z_norm_squared) / log(BAILOUT_SQUARED_SMOOTH);
+    return log2(ratio) / log2(degree);
+}
+
+// Add the term of z to the stripe or triangle inequality average, c is what the formula added
+// to get to z. Stripes follow the angle of z, the triangle inequality term is where |z| lies
+// between the least and the most |z - c| and |c| can add up to, and is left out if they are equal.
+fn add_average_term(average: Average, z: vec2<f32>, c: vec2<f32>) -> Average {
+    var term: f32;
+    if (my_uniform.coloring_mode == COLORING_STRIPE) {
+        term = 0.5 + 0.5 * sin(my_uniform.stripe_density * atan2(z.y, z.x));
     } else {
-        return vec3<f32>(chroma + m, m, x + m);
+        let w_norm = length(z - c);
+        let c_norm = length(c);
+        let low = abs(w_norm - c_norm);
+        let high = w_norm + c_norm;
+        if (high <= low) {
+            return average;
+        }
+        term = (length(z) - low) / (high - low);
+    }
+    return Average(average.sum + term, term, average.count + 1.0);
+}
+
+// Average of the terms up to the last iteration, that escaped with |z|^2 = z_norm_squared,
+// interpolated towards the average without it as the normalized iteration count is
+fn get_average(average: Average, z_norm_squared: f32) -> f32 {
+    if (average.count < 2.0) {
+        return average.sum;
+    }
+    let last = average.sum / average.count;
+    let before = (average.sum - average.last) / (average.count - 1.0);
+    return mix(last, before, clamp(get_iteration_excess(z_norm_squared), 0.0, 1.0));
+}
+
+// derivative_log2 is log2 of |dz/dc|, the derivative of the last z with respect to the point
+// of the view in units of 2^exponent, only needed when colouring by distance
+fn get_escape(
+    i: i32,
+    z_norm_squared: f32,
+    derivative_log2: f32,
+    trap: f32,
+    interior: vec2<f32>,
+    average: Average,
+) -> Escape {
+    let fraction = get_fraction(i, z_norm_squared);
+    if (my_uniform.coloring_mode != COLORING_DISTANCE || fraction >= 1.0) {
+        return Escape(fraction, 1.0, trap, interior, get_average(average, z_norm_squared));
+    }
+    // the distance estimate |z| log|z| / 2 |dz/dc|, in logarithms to stay within the range of f32
+    let z_log = 0.5 * log(z_norm_squared);
+    let distance_log2 = 0.5 * log2(z_norm_squared) + log2(0.5 * z_log) - derivative_log2;
+    return Escape(fraction, exp2(distance_log2 - log2(pixel_size)), trap, interior, 0.0);
+}
+
+// Palette colour, darkened within a pixel of the set when colouring by distance so that
+// filaments thinner than a pixel show up as anti-aliased lines
+fn get_escape_color(escape: Escape) -> vec3<f32> {
+    if (my_uniform.coloring_mode == COLORING_TRAP) {
+        return get_trap_color(escape.trap);
     }
+    if (escape.fraction >= 1.0) {
+        return get_interior_color(escape.interior);
+    }
+    if (my_uniform.coloring_mode == COLORING_STRIPE || my_uniform.coloring_mode == COLORING_TRIANGLE) {
+        let position = fract(escape.average + my_uniform.palette_offset);
+        return textureSampleLevel(palette_texture, palette_sampler, vec2<f32>(position, 0.5), 0.0).rgb;
+    }
+    return get_color(escape.fraction) * clamp(escape.distance, 0.0, 1.0);
 }
 
-fn get_color(fraction_: f32, time: f32) -> vec3<f32> {
-    if (fraction_ >= 1.0) {
+// Distance from z to the orbit trap
+fn get_trap_distance(z: vec2<f32>) -> f32 {
+    let d = z - my_uniform.trap_position;
+    if (my_uniform.trap_shape == TRAP_LINE) {
+        return abs(d.y);
+    }
+    if (my_uniform.trap_shape == TRAP_CROSS) {
+        return min(abs(d.x), abs(d.y));
+    }
+    if (my_uniform.trap_shape == TRAP_CIRCLE) {
+        return abs(length(d) - my_uniform.trap_radius);
+    }
+    return length(d);
+}
+
+// Colour of a point from the closest its orbit came to the trap, inside the set as well
+fn get_trap_color(trap: f32) -> vec3<f32> {
+    let octaves = -log2(max(trap, 1e-30));
+    let position = fract(octaves / TRAP_PALETTE_PERIOD + my_uniform.palette_offset);
+    return textureSampleLevel(palette_texture, palette_sampler, vec2<f32>(position, 0.5), 0.0).rgb;
+}
+
+// Squared distance below which an orbit counts as having come back to an earlier point
+fn get_period_tolerance_squared() -> f32 {
+    let pixel = pixel_size * exp2(f32(my_uniform.exponent));
+    return min(PERIOD_TOLERANCE_SQUARED, pixel * pixel);
+}
+
+// Palette position before palette_offset and brightness of a point that does not escape,
+// from the last z of its orbit and the period of the cycle it is attracted to, 0 if none was found
+fn get_interior(z: vec2<f32>, c: vec2<f32>, period: i32) -> vec2<f32> {
+    let mode = my_uniform.interior_mode;
+    if (mode == INTERIOR_FINAL_Z) {
+        return vec2<f32>(fract(0.5 * length(z)), 1.0);
+    }
+    if (period == 0 || mode == INTERIOR_BLACK) {
+        return vec2<f32>(0.0, 0.0);
+    }
+
+    // while the orbit is still converging the check can find a multiple of the period,
+    // it is the first of its divisors after which the orbit comes back close to z.
+    // The multiplier of the cycle is the product of the derivatives of the formula around it.
+    let custom = my_uniform.fractal_kind / 2 == FORMULA_CUSTOM;
+    let tolerance_squared = PERIOD_RETURN_FACTOR * get_period_tolerance_squared();
+    var w = z;
+    var multiplier = vec2<f32>(1.0, 0.0);
+    var cycle = period;
+    for (var k: i32 = 1; k <= period; k = k + 1) {
+        if (custom) {
+            w = custom_formula(w, c);
+        } else {
+            multiplier = formula_derivative(w, multiplier);
+            w = apply_formula(w) + c;
+        }
+        if (period % k == 0 && dot(w - z, w - z) < tolerance_squared) {
+            cycle = k;
+            break;
+        }
+    }
+
+    if (mode == INTERIOR_PERIOD) {
+        return vec2<f32>(fract(f32(cycle - 1) * INTERIOR_PERIOD_STEP), 1.0);
+    }
+    if (custom) {
+        return vec2<f32>(0.0, 0.0);
+    }
+    // its angle picks the colour and its magnitude, below 1 for attracting cycles, the brightness
+    let angle = atan2(multiplier.y, multiplier.x) / TAU;
+    return vec2<f32>(fract(angle), min(length(multiplier), 1.0));
+}
+
+// Colour of a point that does not escape, black unless an interior mode is chosen
+fn get_interior_color(interior: vec2<f32>) -> vec3<f32> {
+    if (my_uniform.interior_mode == INTERIOR_BLACK) {
         return vec3<f32>(0.0, 0.0, 0.0);
     }
+    let position = vec2<f32>(fract(interior.x + my_uniform.palette_offset), 0.5);
+    return textureSampleLevel(palette_texture, palette_sampler, position, 0.0).rgb * interior.y;
+}
+
+// power as a whole number from 2 to POWER_INTEGER_MAX, 0 if it is not one
+// (same as Uniform::get_integer_power)
+fn get_integer_power() -> i32 {
+    let n = i32(my_uniform.power.x);
+    if (my_uniform.power.y == 0.0 && f32(n) == my_uniform.power.x && n >= 2 && n <= POWER_INTEGER_MAX) {
+        return n;
+    }
+    return 0;
+}
+
+fn complex_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+fn complex_div(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(a.x * b.x + a.y * b.y, a.y * b.x - a.x * b.y) / dot(b, b);
+}
+
+fn complex_square(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>((z.x * z.x) - (z.y * z.y), 2.0 * z.x * z.y);
+}
+
+// z^n for n >= 2
+fn complex_pow(z: vec2<f32>, n: i32) -> vec2<f32> {
+    var w = complex_square(z);
+    for (var k: i32 = 2; k < n; k = k + 1) {
+        w = complex_mul(w, z);
+    }
+    return w;
+}
+
+// z^d = exp(d log z) for any complex d, with the principal branch of the logarithm
+fn complex_pow_polar(z: vec2<f32>, d: vec2<f32>) -> vec2<f32> {
+    if (z.x == 0.0 && z.y == 0.0) {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let log_z = vec2<f32>(0.5 * log(dot(z, z)), atan2(z.y, z.x));
+    let w = complex_mul(d, log_z);
+    return exp(w.x) * vec2<f32>(cos(w.y), sin(w.y));
+}
+
+// Functions of custom formulas, complex_ and their name in Function of expression.rs
+
+fn complex_exp(z: vec2<f32>) -> vec2<f32> {
+    return exp(z.x) * vec2<f32>(cos(z.y), sin(z.y));
+}
+
+fn complex_log(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(0.5 * log(dot(z, z)), atan2(z.y, z.x));
+}
+
+fn complex_sqrt(z: vec2<f32>) -> vec2<f32> {
+    let r = length(z);
+    let im = sqrt(max(0.5 * (r - z.x), 0.0));
+    return vec2<f32>(sqrt(max(0.5 * (r + z.x), 0.0)), select(im, -im, z.y < 0.0));
+}
+
+fn complex_sin(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(sin(z.x) * cosh(z.y), cos(z.x) * sinh(z.y));
+}
+
+fn complex_cos(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(cos(z.x) * cosh(z.y), -sin(z.x) * sinh(z.y));
+}
+
+fn complex_tan(z: vec2<f32>) -> vec2<f32> {
+    return complex_div(complex_sin(z), complex_cos(z));
+}
+
+fn complex_sinh(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(sinh(z.x) * cos(z.y), cosh(z.x) * sin(z.y));
+}
+
+fn complex_cosh(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(cosh(z.x) * cos(z.y), sinh(z.x) * sin(z.y));
+}
+
+fn complex_tanh(z: vec2<f32>) -> vec2<f32> {
+    return complex_div(complex_sinh(z), complex_cosh(z));
+}
+
+fn complex_conj(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(z.x, -z.y);
+}
+
+fn complex_abs(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(length(z), 0.0);
+}
+
+fn complex_re(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(z.x, 0.0);
+}
+
+fn complex_im(z: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(z.y, 0.0);
+}
+
+// Every formula is f(z) = formula_after(formula_before(z)^power), z -> f(z) + c
+
+fn formula_before(z: vec2<f32>) -> vec2<f32> {
+    let formula = my_uniform.fractal_kind / 2;
+    if (formula == FORMULA_BURNING_SHIP) {
+        return abs(z);
+    }
+    if (formula == FORMULA_TRICORN) {
+        return vec2<f32>(z.x, -z.y);
+    }
+    if (formula == FORMULA_PERPENDICULAR) {
+        return vec2<f32>(abs(z.x), -z.y);
+    }
+    return z;
+}
+
+fn formula_after(w: vec2<f32>) -> vec2<f32> {
+    let formula = my_uniform.fractal_kind / 2;
+    if (formula == FORMULA_CELTIC) {
+        return vec2<f32>(abs(w.x), w.y);
+    }
+    if (formula == FORMULA_BUFFALO) {
+        return vec2<f32>(abs(w.x), -abs(w.y));
+    }
+    return w;
+}
+
+// f(z) of the formula, z -> f(z) + c
+fn apply_formula(z: vec2<f32>) -> vec2<f32> {
+    let n = get_integer_power();
+    let before = formula_before(z);
+    if (n == 0) {
+        return formula_after(complex_pow_polar(before, my_uniform.power));
+    }
+    return formula_after(complex_pow(before, n));
+}
+
+// Derivative of the formula at z applied to dz. The reflections of formula_before and
+// formula_after reflect dz the same way, and d/dz z^d = d z^d / z.
+fn formula_derivative(z: vec2<f32>, dz: vec2<f32>) -> vec2<f32> {
+    let formula = my_uniform.fractal_kind / 2;
+    let before = formula_before(z);
+    let z_sign = select(vec2<f32>(1.0), vec2<f32>(-1.0), z < vec2<f32>(0.0));
+    var d_before = dz;
+    if (formula == FORMULA_BURNING_SHIP) {
+        d_before = dz * z_sign;
+    } else if (formula == FORMULA_TRICORN) {
+        d_before = vec2<f32>(dz.x, -dz.y);
+    } else if (formula == FORMULA_PERPENDICULAR) {
+        d_before = vec2<f32>(dz.x * z_sign.x, -dz.y);
+    }
+
+    if (before.x == 0.0 && before.y == 0.0) {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let n = get_integer_power();
+    var w: vec2<f32>;
+    if (n == 0) {
+        w = complex_pow_polar(before, my_uniform.power);
+    } else {
+        w = complex_pow(before, n);
+    }
+    let dw = complex_mul(complex_mul(my_uniform.power, complex_div(w, before)), d_before);
+
+    let w_sign = select(vec2<f32>(1.0), vec2<f32>(-1.0), w < vec2<f32>(0.0));
+    if (formula == FORMULA_CELTIC) {
+        return vec2<f32>(dw.x * w_sign.x, dw.y);
+    }
+    if (formula == FORMULA_BUFFALO) {
+        return vec2<f32>(dw.x * w_sign.x, -dw.y * w_sign.y);
+    }
+    return dw;
+}
+
+// Formula typed in by the user, z -> f(z, c). The program replaces the body with the one
+// generated from the formula when it builds the pipeline, as written it is the default z^2 + c
+fn custom_formula(z: vec2<f32>, c: vec2<f32>) -> vec2<f32> {
+    return complex_square(z) + c;
+}
 
-    let pi: f32 = 3.14159265358979323846264338327950288419716939937510582097494459;
-    let fraction = sin(fraction_ * pi * 5.0);
+// The derivative of z with respect to the point of the view starts at derivative_start
+// and derivative_c is that of c, one of them is 2^exponent and the other zero. It grows
+// beyond f32 near the boundary of the set, so it is kept with a separate exponent.
+fn julia(z_start: vec2<f32>, c: vec2<f32>, derivative_start: vec2<f32>, derivative_c: vec2<f32>) -> Escape {
 
-    let period: f32 = 30.0; // seconds
-    let oscillating = (time / period ) % 1.0;
+    let iterations_max: i32 = i32(my_uniform.max_iterations);
+    let bailout_squared = get_bailout_squared();
+    let custom = my_uniform.fractal_kind / 2 == FORMULA_CUSTOM;
+    let distance = my_uniform.coloring_mode == COLORING_DISTANCE;
+    let averaging = my_uniform.coloring_mode == COLORING_STRIPE || my_uniform.coloring_mode == COLORING_TRIANGLE;
+    var z: vec2<f32> = z_start;
+    var derivative = fe_from(derivative_start, 0);
+    let derivative_c_fe = fe_from(derivative_c, 0);
+    var trap = TRAP_DISTANCE_MAX;
+    var average = Average(0.0, 0.0, 0.0);
+    // Brent's periodicity check, z is compared with the point at the last power of two
+    let tolerance_squared = get_period_tolerance_squared();
+    var check = z_start;
+    var check_i: i32 = 0;
+    var period: i32 = 0;
+    var i: i32 = 0;
+    for (; i < iterations_max; i = i + 1) {
+        // the averages take the points after the start of the orbit, up to the escaping one
+        if (averaging && i > 0) { average = add_average_term(average, z, c); }
+        if (dot(z, z) > bailout_squared) { break; }
+        // the orbit trap measures the points after the start of the orbit
+        if (i > 0) { trap = min(trap, get_trap_distance(z)); }
+        if (i > 0 && dot(z - check, z - check) < tolerance_squared) {
+            // the point will never escape, stop early
+            period = i - check_i;
+            i = iterations_max;
+            break;
+        }
+        if ((i & (i - 1)) == 0) {
+            check = z;
+            check_i = i;
+        }
+        if (custom) {
+            z = custom_formula(z, c);
+        } else {
+            if (distance) {
+                derivative = fe_add(fe_from(formula_derivative(z, derivative.m), derivative.e), derivative_c_fe);
+            }
+            z = apply_formula(z) + c;
+        }
+    }
 
-    let color_end: vec3<f32> = hsv2rgb(oscillating, 1.0, 1.0);
-    let color_begin: vec3<f32> = hsv2rgb((oscillating + 0.5) % 1.0, 1.0, 1.0);
+    if (custom) {
+        // there is no derivative of custom formulas, they are coloured as smooth
+        let interior = get_interior(z, c, period);
+        return Escape(get_fraction(i, dot(z, z)), 1.0, trap, interior, get_average(average, dot(z, z)));
+    }
+    let interior = get_interior(z, c, period);
+    return get_escape(i, dot(z, z), fe_log2_abs(derivative), trap, interior, average);
+}
 
-    return color_begin * fraction + color_end * (1.0 - fraction);
+// Root of the polynomial a point converges to, -1 if it does not, and how many iterations it took
+struct NewtonResult {
+    root: i32,
+    iterations: f32,
 }
 
-fn julia(z_start: vec2<f32>, c: vec2<f32>) -> f32 {
+// z -> z - a p(z) / p'(z) + c, or Halley's z -> z - a p / (p' - p p'' / (2 p')) + c
+fn newton(z_start: vec2<f32>, c: vec2<f32>) -> NewtonResult {
 
-    let iterations_max: i32 = 255;
+    let iterations_max: i32 = i32(my_uniform.max_iterations);
+    let degree = i32(my_uniform.degree);
     var z: vec2<f32> = z_start;
+    var step_norm_squared: f32 = 1.0;
     var i: i32 = 0;
     for (; i < iterations_max; i = i + 1) {
-        if (dot(z, z) > 4.0) { break; }
-        z = vec2<f32>((z.x * z.x) - (z.y * z.y) + c.x, (2.0 * z.x * z.y) + c.y);
+        // p, p' and p'' / 2 by Horner's rule
+        var p = my_uniform.polynomial[degree].xy;
+        var dp = vec2<f32>(0.0, 0.0);
+        var ddp_half = vec2<f32>(0.0, 0.0);
+        for (var k: i32 = degree - 1; k >= 0; k = k - 1) {
+            ddp_half = complex_mul(ddp_half, z) + dp;
+            dp = complex_mul(dp, z) + p;
+            p = complex_mul(p, z) + my_uniform.polynomial[k].xy;
+        }
+        if (dot(dp, dp) == 0.0) {
+            return NewtonResult(-1, 0.0);
+        }
+
+        var step = complex_div(p, dp);
+        if (my_uniform.newton_method == NEWTON_METHOD_HALLEY) {
+            step = complex_div(p, dp - complex_div(complex_mul(p, ddp_half), dp));
+        }
+        let z_next = z - complex_mul(my_uniform.relaxation, step) + c;
+        step_norm_squared = dot(z_next - z, z_next - z);
+        z = z_next;
+        if (step_norm_squared < NEWTON_TOLERANCE_SQUARED) { break; }
+    }
+    if (i >= iterations_max) {
+        return NewtonResult(-1, 0.0);
+    }
+
+    var root: i32 = 0;
+    for (var k: i32 = 1; k < degree; k = k + 1) {
+        let to_root = my_uniform.polynomial[k].zw - z;
+        let to_nearest = my_uniform.polynomial[root].zw - z;
+        if (dot(to_root, to_root) < dot(to_nearest, to_nearest)) {
+            root = k;
+        }
     }
 
-    return f32(i) / f32(iterations_max);
+    if (my_uniform.coloring_mode == COLORING_BANDED) {
+        return NewtonResult(root, f32(i));
+    }
+    // near a simple root the number of correct digits doubles on every Newton step and
+    // triples on every Halley step, so the count is made continuous as in get_fraction
+    var order = 2.0;
+    if (my_uniform.newton_method == NEWTON_METHOD_HALLEY) {
+        order = 3.0;
+    }
+    let ratio = log(step_norm_squared) / log(NEWTON_TOLERANCE_SQUARED);
+    return NewtonResult(root, max(f32(i) - log2(ratio) / log2(order), 0.0));
 }
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+// Colour of the basin of the root from the palette, darker the longer it took to get there
+fn get_newton_color(result: NewtonResult) -> vec3<f32> {
+    if (result.root < 0) {
+        return vec3<f32>(0.0, 0.0, 0.0);
+    }
+    let position = fract((f32(result.root) + 0.5) / f32(my_uniform.degree) + my_uniform.palette_offset);
+    let color = textureSampleLevel(palette_texture, palette_sampler, vec2<f32>(position, 0.5), 0.0).rgb;
+    return color * exp(-result.iterations / NEWTON_SHADING);
+}
+
+// Double-single (float-float) arithmetic, a number is stored as the unevaluated sum hi + lo.
+// Shader compilers treat float addition as associative, regrouping sums and simplifying
+// s - (s - a) to a, which throws away the low part. Multiplying by my_uniform.one (always 1.0)
+// after every step whose rounding matters keeps them from seeing through the expressions.
+
+fn ds_two_sum(a: f32, b: f32) -> vec2<f32> {
+    let one = my_uniform.one;
+    let s = (a + b) * one;
+    let v = (s - a) * one;
+    let u = (s - v) * one;
+    return vec2<f32>(s, (a - u) * one + (b - v) * one);
+}
+
+fn ds_quick_two_sum(a: f32, b: f32) -> vec2<f32> {
+    let one = my_uniform.one;
+    let s = (a + b) * one;
+    let v = (s - a) * one;
+    return vec2<f32>(s, b - v);
+}
+
+fn ds_split(a: f32) -> vec2<f32> {
+    let one = my_uniform.one;
+    let t = 4097.0 * a;
+    let hi = (t - (t - a) * one) * one;
+    return vec2<f32>(hi, a - hi);
+}
+
+fn ds_two_product(a: f32, b: f32) -> vec2<f32> {
+    let one = my_uniform.one;
+    let p = a * b;
+    let a_split = ds_split(a);
+    let b_split = ds_split(b);
+    var e = (a_split.x * b_split.x - p) * one;
+    e = (e + a_split.x * b_split.y) * one;
+    e = (e + a_split.y * b_split.x) * one;
+    return vec2<f32>(p, e + a_split.y * b_split.y);
+}
+
+fn ds_add(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let s = ds_two_sum(a.x, b.x);
+    return ds_quick_two_sum(s.x, s.y + a.y + b.y);
+}
+
+fn ds_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let p = ds_two_product(a.x, b.x);
+    return ds_quick_two_sum(p.x, p.y + a.x * b.y + a.y * b.x);
+}
+
+fn ds_abs(a: vec2<f32>) -> vec2<f32> {
+    if (a.x < 0.0) {
+        return -a;
+    }
+    return a;
+}
+
+// complex numbers with the real part in xy and the imaginary part in zw
+fn ds_complex_mul(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
+    let re = ds_add(ds_mul(a.xy, b.xy), -ds_mul(a.zw, b.zw));
+    let im = ds_add(ds_mul(a.xy, b.zw), ds_mul(a.zw, b.xy));
+    return vec4<f32>(re, im);
+}
+
+// same as apply_formula, only for integer powers (other powers are always drawn in single precision)
+fn apply_formula_double_single(x: vec2<f32>, y: vec2<f32>) -> vec4<f32> {
+    let formula = my_uniform.fractal_kind / 2;
+    var before = vec4<f32>(x, y);
+    if (formula == FORMULA_BURNING_SHIP) {
+        before = vec4<f32>(ds_abs(x), ds_abs(y));
+    } else if (formula == FORMULA_TRICORN) {
+        before = vec4<f32>(x, -y);
+    } else if (formula == FORMULA_PERPENDICULAR) {
+        before = vec4<f32>(ds_abs(x), -y);
+    }
+
+    let xy = ds_mul(before.xy, before.zw);
+    var w = vec4<f32>(ds_add(ds_mul(before.xy, before.xy), -ds_mul(before.zw, before.zw)), ds_add(xy, xy));
+    for (var k: i32 = 2; k < get_integer_power(); k = k + 1) {
+        w = ds_complex_mul(w, before);
+    }
+
+    if (formula == FORMULA_CELTIC) {
+        return vec4<f32>(ds_abs(w.xy), w.zw);
+    }
+    if (formula == FORMULA_BUFFALO) {
+        return vec4<f32>(ds_abs(w.xy), -ds_abs(w.zw));
+    }
+    return w;
+}
+
+// same as julia but with each coordinate in double-single precision
+// (the derivative needs no more than single precision, with its separate exponent)
+fn julia_double_single(
+    z_start_x: vec2<f32>,
+    z_start_y: vec2<f32>,
+    c_x: vec2<f32>,
+    c_y: vec2<f32>,
+    derivative_start: vec2<f32>,
+    derivative_c: vec2<f32>,
+) -> Escape {
+
+    let iterations_max: i32 = i32(my_uniform.max_iterations);
+    let bailout_squared = get_bailout_squared();
+    let distance = my_uniform.coloring_mode == COLORING_DISTANCE;
+    let averaging = my_uniform.coloring_mode == COLORING_STRIPE || my_uniform.coloring_mode == COLORING_TRIANGLE;
+    var x: vec2<f32> = z_start_x;
+    var y: vec2<f32> = z_start_y;
+    var derivative = fe_from(derivative_start, 0);
+    let derivative_c_fe = fe_from(derivative_c, 0);
+    var trap = TRAP_DISTANCE_MAX;
+    var average = Average(0.0, 0.0, 0.0);
+    let tolerance_squared = get_period_tolerance_squared();
+    var check_x = z_start_x;
+    var check_y = z_start_y;
+    var check_i: i32 = 0;
+    var period: i32 = 0;
+    var i: i32 = 0;
+    for (; i < iterations_max; i = i + 1) {
+        if (averaging && i > 0) {
+            average = add_average_term(average, vec2<f32>(x.x, y.x), vec2<f32>(c_x.x, c_y.x));
+        }
+        if (x.x * x.x + y.x * y.x > bailout_squared) { break; }
+        if (i > 0) { trap = min(trap, get_trap_distance(vec2<f32>(x.x, y.x))); }
+        let d = vec2<f32>(ds_add(x, -check_x).x, ds_add(y, -check_y).x);
+        if (i > 0 && dot(d, d) < tolerance_squared) {
+            period = i - check_i;
+            i = iterations_max;
+            break;
+        }
+        if ((i & (i - 1)) == 0) {
+            check_x = x;
+            check_y = y;
+            check_i = i;
+        }
+        if (distance) {
+            derivative = fe_add(fe_from(formula_derivative(vec2<f32>(x.x, y.x), derivative.m), derivative.e), derivative_c_fe);
+        }
+        let f = apply_formula_double_single(x, y);
+        x = ds_add(f.xy, c_x);
+        y = ds_add(f.zw, c_y);
+    }
+
+    let interior = get_interior(vec2<f32>(x.x, y.x), vec2<f32>(c_x.x, c_y.x), period);
+    return get_escape(i, x.x * x.x + y.x * y.x, fe_log2_abs(derivative), trap, interior, average);
+}
+
+// Complex number with a separate exponent, value = m * 2^e, to go beyond the range of f32
+
+struct FloatExp {
+    m: vec2<f32>,
+    e: i32,
+}
+
+const FLOAT_EXP_ZERO: i32 = -100000;
+
+fn fe_normalize(a: FloatExp) -> FloatExp {
+    let m_max = max(abs(a.m.x), abs(a.m.y));
+    if (m_max == 0.0) {
+        return FloatExp(vec2<f32>(0.0, 0.0), FLOAT_EXP_ZERO);
+    }
+    let k = i32(floor(log2(m_max)));
+    return FloatExp(ldexp(a.m, vec2<i32>(-k, -k)), a.e + k);
+}
+
+fn fe_from(v: vec2<f32>, e: i32) -> FloatExp {
+    return fe_normalize(FloatExp(v, e));
+}
+
+fn fe_shift(m: vec2<f32>, k: i32) -> vec2<f32> {
+    // anything shifted further than this is below f32 precision anyway
+    let k_clamped = max(k, -160);
+    return ldexp(m, vec2<i32>(k_clamped, k_clamped));
+}
+
+fn fe_add(a: FloatExp, b: FloatExp) -> FloatExp {
+    let e = max(a.e, b.e);
+    return fe_normalize(FloatExp(fe_shift(a.m, a.e - e) + fe_shift(b.m, b.e - e), e));
+}
+
+fn fe_mul(a: FloatExp, b: FloatExp) -> FloatExp {
+    return fe_normalize(FloatExp(complex_mul(a.m, b.m), a.e + b.e));
+}
+
+fn fe_scale(a: FloatExp, s: f32) -> FloatExp {
+    return fe_normalize(FloatExp(a.m * s, a.e));
+}
+
+// real and imaginary parts as real numbers
+fn fe_real(a: FloatExp) -> FloatExp {
+    return fe_normalize(FloatExp(vec2<f32>(a.m.x, 0.0), a.e));
+}
+
+fn fe_imag(a: FloatExp) -> FloatExp {
+    return fe_normalize(FloatExp(vec2<f32>(a.m.y, 0.0), a.e));
+}
+
+// complex number from two real ones
+fn fe_complex(re: FloatExp, im: FloatExp) -> FloatExp {
+    return fe_add(FloatExp(vec2<f32>(re.m.x, 0.0), re.e), FloatExp(vec2<f32>(0.0, im.m.x), im.e));
+}
+
+// |c + d| - |c| for real c and d, without the cancellation of subtracting when d is tiny
+fn fe_diffabs(c: f32, d: FloatExp) -> FloatExp {
+    let c_fe = fe_from(vec2<f32>(c, 0.0), 0);
+    let sum = fe_add(c_fe, d);
+    if (c >= 0.0) {
+        if (sum.m.x >= 0.0) {
+            return d;
+        }
+        return fe_scale(fe_add(sum, c_fe), -1.0);
+    }
+    if (sum.m.x > 0.0) {
+        return fe_add(sum, c_fe);
+    }
+    return fe_scale(d, -1.0);
+}
+
+fn fe_to_f32(a: FloatExp) -> vec2<f32> {
+    return fe_shift(a.m, min(a.e, 120));
+}
+
+fn fe_log2_abs(a: FloatExp) -> f32 {
+    return f32(a.e) + 0.5 * log2(dot(a.m, a.m));
+}
+
+fn reference_point(n: u32) -> vec2<f32> {
+    let width = textureDimensions(orbit_texture).x;
+    return textureLoad(orbit_texture, vec2<u32>(n % width, n / width), 0).xy;
+}
+
+// f(Z + dz) - f(Z) + dc for the reference point Z = X + iY and the formula, only for
+// integer powers n, the absolute values of the non-analytic formulas go through fe_diffabs
+fn perturbation_step(z_reference: vec2<f32>, dz: FloatExp, dc: FloatExp) -> FloatExp {
+    let formula = my_uniform.fractal_kind / 2;
+    let n = get_integer_power();
+    let x = z_reference.x;
+    let y = z_reference.y;
+
+    // B = formula_before(Z) and dB = formula_before(Z + dz) - B
+    let b = formula_before(z_reference);
+    var db = dz;
+    if (formula == FORMULA_BURNING_SHIP) {
+        db = fe_complex(fe_diffabs(x, fe_real(dz)), fe_diffabs(y, fe_imag(dz)));
+    } else if (formula == FORMULA_TRICORN) {
+        db = FloatExp(vec2<f32>(dz.m.x, -dz.m.y), dz.e);
+    } else if (formula == FORMULA_PERPENDICULAR) {
+        db = fe_complex(fe_diffabs(x, fe_real(dz)), fe_scale(fe_imag(dz), -1.0));
+    }
+
+    // (B + dB)^n - B^n = sum of binomial(n, k) B^(n - k) dB^k for k from 1 to n, by Horner's
+    // rule: dB (binomial(n, 1) B^(n - 1) + dB (binomial(n, 2) B^(n - 2) + ... + dB))
+    let b_fe = fe_from(b, 0);
+    var b_power = fe_from(vec2<f32>(1.0, 0.0), 0);
+    var sum = b_power;
+    var binomial = 1.0;
+    for (var k: i32 = n - 1; k >= 1; k = k - 1) {
+        b_power = fe_mul(b_power, b_fe);
+        binomial = binomial * f32(k + 1) / f32(n - k);
+        sum = fe_add(fe_scale(b_power, binomial), fe_mul(db, sum));
+    }
+    var d = fe_mul(db, sum);
+
+    // formula_after(B^n + d) - formula_after(B^n)
+    if (formula == FORMULA_CELTIC || formula == FORMULA_BUFFALO) {
+        let w = complex_pow(b, n);
+        var im = fe_imag(d);
+        if (formula == FORMULA_BUFFALO) {
+            im = fe_scale(fe_diffabs(w.y, im), -1.0);
+        }
+        d = fe_complex(fe_diffabs(w.x, fe_real(d)), im);
+    }
+    return fe_add(d, dc);
+}
+
+// Iterate the difference between a point and the reference orbit Z:
+// z = Z + dz, dz -> f(Z + dz) - f(Z) + dc
+// and the derivative as in julia, with a separate exponent as it grows beyond f32 in deep zooms.
+// There is no periodicity check, z in single precision cannot tell a cycle from an orbit
+// that stays close to one for a while, so only the final z colours the interior.
+fn julia_perturbation(dz_start: FloatExp, dc: FloatExp, derivative_start: FloatExp, derivative_c: FloatExp) -> Escape {
+
+    let iterations_max: i32 = i32(my_uniform.max_iterations);
+    let bailout_squared = get_bailout_squared();
+    let distance = my_uniform.coloring_mode == COLORING_DISTANCE;
+    let averaging = my_uniform.coloring_mode == COLORING_STRIPE || my_uniform.coloring_mode == COLORING_TRIANGLE;
+    // c in single precision is enough for the triangle inequality average,
+    // the reference orbit of parameter planes is that of the center
+    let c = select(my_uniform.c, my_uniform.center, my_uniform.fractal_kind % 2 == 1) + fe_to_f32(dc);
+    let z_start = reference_point(0u);
+    var dz: FloatExp = dz_start;
+    var derivative = derivative_start;
+    var trap = TRAP_DISTANCE_MAX;
+    var average = Average(0.0, 0.0, 0.0);
+    var n: u32 = 0u;
+    var z_norm_squared: f32 = 0.0;
+    var i: i32 = 0;
+    for (; i < iterations_max; i = i + 1) {
+        var z_reference = reference_point(n);
+        let z = z_reference + fe_to_f32(dz);
+        z_norm_squared = dot(z, z);
+        if (averaging && i > 0) { average = add_average_term(average, z, c); }
+        if (z_norm_squared > bailout_squared) { break; }
+        if (i > 0) { trap = min(trap, get_trap_distance(z)); }
+        if (distance) {
+            derivative = fe_add(fe_from(formula_derivative(z, derivative.m), derivative.e), derivative_c);
+        }
+
+        // rebase to the start of the reference orbit when it runs out
+        // or when the point gets closer to its start than to the reference
+        let dz_rebased = fe_add(fe_from(z_reference - z_start, 0), dz);
+        if (n + 1u >= my_uniform.reference_length || fe_log2_abs(dz_rebased) < fe_log2_abs(dz)) {
+            dz = dz_rebased;
+            n = 0u;
+            z_reference = z_start;
+        }
+
+        dz = perturbation_step(z_reference, dz, dc);
+        n = n + 1u;
+    }
+
+    let z = reference_point(n) + fe_to_f32(dz);
+    let interior = get_interior(z, vec2<f32>(0.0, 0.0), 0);
+    return get_escape(i, z_norm_squared, fe_log2_abs(derivative), trap, interior, average);
+}
+
+// Offset from center in units of 2^exponent of the point at position_xy,
+// rotation holds the cosine and sine of the angle
+fn get_offset(position_xy: vec2<f32>) -> vec2<f32> {
+    let offset_unrotated: vec2<f32> = position_xy * my_uniform.size / 2.0;
+    return vec2<f32>(
+        offset_unrotated.x * my_uniform.rotation.x - offset_unrotated.y * my_uniform.rotation.y,
+        offset_unrotated.x * my_uniform.rotation.y + offset_unrotated.y * my_uniform.rotation.x,
+    );
+}
+
+// Everything colouring a point takes, which cache.wgsl keeps for every pixel: the fraction,
+// distance, trap and average of its Escape followed by its interior, or for the Newton
+// fractals the root and iterations of its NewtonResult in place of the interior
+struct Point {
+    escape: vec4<f32>,
+    rest: vec4<f32>,
+}
+
+// Iterate the point at offset
+fn get_point(offset: vec2<f32>) -> Point {
     // z -> z * z + c | let z = (a + ib) and c = (c + id) then z * z + c = (a*a - b*b + c) + i(2*a*b + d)
 
-    let domain_size: vec2<f32> = vec2<f32>(my_uniform.domain[1] - my_uniform.domain[0], my_uniform.domain[3] - my_uniform.domain[2]);
-    let domain_center: vec2<f32> = vec2<f32>(my_uniform.domain[1] + my_uniform.domain[0], my_uniform.domain[3] + my_uniform.domain[2]) / 2.0;
+    // Julia sets iterate from the point with c fixed, parameter planes from c with the point as c
+    // the derivative of the point with respect to the offset is 2^exponent
+    let julia = my_uniform.fractal_kind % 2 == 0;
+    var escape: Escape;
+    if (my_uniform.precision_mode == PRECISION_PERTURBATION) {
+        let zero = FloatExp(vec2<f32>(0.0, 0.0), FLOAT_EXP_ZERO);
+        let d = fe_from(offset, my_uniform.exponent);
+        let unit = fe_from(vec2<f32>(1.0, 0.0), my_uniform.exponent);
+        if (julia) {
+            escape = julia_perturbation(d, zero, unit, zero);
+        } else {
+            escape = julia_perturbation(zero, d, zero, unit);
+        }
+        return get_escape_point(escape);
+    }
+
+    let unit = vec2<f32>(exp2(f32(my_uniform.exponent)), 0.0);
+    let zero = vec2<f32>(0.0, 0.0);
 
-    var z: vec2<f32> = vec2<f32>(in.position_xy.x * domain_size.x, in.position_xy.y * domain_size.y) / 2.0 + domain_center;
+    if (my_uniform.precision_mode == PRECISION_DOUBLE_SINGLE) {
+        let offset_scaled = ldexp(offset, vec2<i32>(my_uniform.exponent, my_uniform.exponent));
+        let z_x = ds_add(vec2<f32>(my_uniform.center.x, my_uniform.center_lo.x), vec2<f32>(offset_scaled.x, 0.0));
+        let z_y = ds_add(vec2<f32>(my_uniform.center.y, my_uniform.center_lo.y), vec2<f32>(offset_scaled.y, 0.0));
+        let c_x = vec2<f32>(my_uniform.c.x, my_uniform.c_lo.x);
+        let c_y = vec2<f32>(my_uniform.c.y, my_uniform.c_lo.y);
+        if (julia) {
+            escape = julia_double_single(z_x, z_y, c_x, c_y, unit, zero);
+        } else {
+            escape = julia_double_single(c_x, c_y, z_x, z_y, zero, unit);
+        }
+        return get_escape_point(escape);
+    }
+
+    var z: vec2<f32> = my_uniform.center + ldexp(offset, vec2<i32>(my_uniform.exponent, my_uniform.exponent));
+
+    if (my_uniform.fractal_kind / 2 == FORMULA_NEWTON) {
+        var result: NewtonResult;
+        if (julia) {
+            result = newton(z, my_uniform.c);
+        } else {
+            result = newton(my_uniform.c, z);
+        }
+        return Point(vec4<f32>(0.0), vec4<f32>(0.0, 0.0, f32(result.root), result.iterations));
+    }
 
-    var fraction: f32;
-    if (my_uniform.mandelbrot <= 0) {
-        // use julia set
-        fraction = julia(z, my_uniform.c);
+    if (julia) {
+        escape = julia(z, my_uniform.c, unit, zero);
     } else {
-        fraction = julia(my_uniform.c, z);
+        escape = julia(my_uniform.c, z, zero, unit);
     }
+    return get_escape_point(escape);
+}
 
-    let color = get_color(fraction, my_uniform.time);
+fn get_escape_point(escape: Escape) -> Point {
+    return Point(
+        vec4<f32>(escape.fraction, escape.distance, escape.trap, escape.average),
+        vec4<f32>(escape.interior, 0.0, 0.0),
+    );
+}
 
-    return vec4<f32>(color, 1.0);
+fn get_point_color(point: Point) -> vec3<f32> {
+    if (my_uniform.fractal_kind / 2 == FORMULA_NEWTON) {
+        return get_newton_color(NewtonResult(i32(point.rest.z), point.rest.w));
+    }
+    let escape = Escape(point.escape.x, point.escape.y, point.escape.z, point.rest.xy, point.escape.w);
+    return get_escape_color(escape);
+}
+
+// Iterates and colours every pixel of every frame, where there are no compute shaders
+// for cache.wgsl. Without histogram.wgsl COLORING_HISTOGRAM looks like COLORING_SMOOTH
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // position_xy steps by dpdx.x to the pixel on the right and by dpdy.y to the one below
+    let pixel_step = vec2<f32>(dpdx(in.position_xy).x, dpdy(in.position_xy).y);
+    let offset = get_offset(in.position_xy + pixel_step * my_uniform.sample_offset);
+    pixel_size = length(dpdx(offset));
+    return vec4<f32>(get_point_color(get_point(offset)), 1.0);
 }
@@ -1,35 +1,130 @@
+use std::fmt;
 use std::iter;
+#[cfg(not(target_arch = "wasm32"))]
+use std::num::NonZeroU32;
 
+#[cfg(not(target_arch = "wasm32"))]
+use image::imageops::FilterType;
 use instant::Instant;
-use wgpu::util::DeviceExt;
 use wgpu::Gles3MinorVersion;
 use winit::{event::*, window::Window};
 
-use crate::uniform::Uniform;
-use crate::vertex::{Vertex, INDICES, VERTICES};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::cpu;
+use crate::expression::Expression;
+use crate::fractal::{Formula, FractalKind};
+use crate::palette::{Palette, PaletteError};
+use crate::refinement::{get_refinement_key, Blit, Refinement};
+use crate::renderer::{request_device, Renderer};
+use crate::sampling::{Sampling, ACCUMULATION_SAMPLES};
+use crate::scene::{Scene, SceneError};
+use crate::uniform::{
+    Uniform, COLORING_BANDED, COLORING_DISTANCE, COLORING_HISTOGRAM, COLORING_SMOOTH,
+    COLORING_STRIPE, COLORING_TRAP, COLORING_TRIANGLE, INTERIOR_BLACK, INTERIOR_FINAL_Z,
+    INTERIOR_MULTIPLIER, INTERIOR_PERIOD, MAX_ITERATIONS_LIMIT, MAX_ITERATIONS_MIN,
+    NEWTON_METHOD_HALLEY, NEWTON_METHOD_NEWTON, POWER_INTEGER_MAX, TRAP_CIRCLE, TRAP_CROSS,
+    TRAP_LINE, TRAP_POINT,
+};
+use crate::viewport::{Viewport, PRECISION_WARNING};
 
-pub struct State {
+#[derive(Debug)]
+pub enum StateError {
+    NoAdapter,
+    /// Neither a graphics adapter nor a software surface for the window is available.
+    Software(softbuffer::SoftBufferError),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NoAdapter => write!(f, "no usable graphics adapter"),
+            StateError::Software(error) => {
+                write!(f, "no usable graphics adapter, nor CPU fallback: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Seconds it takes the palette to cycle once while cycling is enabled.
+const PALETTE_CYCLE_PERIOD: f32 = 30.0;
+
+/// Parameter file written and read by the S and L keys, in the working directory. The web
+/// build has no file system, so the keys do nothing there.
+#[cfg(not(target_arch = "wasm32"))]
+const SCENE_FILE_NAME: &str = "view.toml";
+
+/// Angle in radians the view is rotated by on each key press.
+const ROTATION_STEP: f64 = std::f64::consts::PI / 36.0;
+
+/// Draws to the window with the GPU.
+struct Gpu {
     surface: wgpu::Surface,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
+    renderer: Renderer,
+    /// Scales frames rendered at another size than the window over it.
+    blit: Blit,
+}
+
+/// What draws to the window.
+enum Backend {
+    Gpu(Box<Gpu>),
+    /// There is no usable graphics adapter, frames are rendered by `cpu::render`.
+    #[cfg(not(target_arch = "wasm32"))]
+    Cpu {
+        _context: softbuffer::Context,
+        surface: softbuffer::Surface,
+        /// Average of the samples so far, in the RGB of each pixel, see `Refinement::Sample`.
+        average: Vec<[f32; 3]>,
+    },
+}
+
+pub struct State {
+    backend: Backend,
     pub size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
     instant: Instant,
     c_from_mouse: bool,
+    /// Set `uniform.power` from the mouse position, like `c` with `c_from_mouse`.
+    power_from_mouse: bool,
+    /// Set `uniform.trap_position` from the mouse position, like `c` with `c_from_mouse`.
+    trap_from_mouse: bool,
     dragging: bool,
     dragging_position_original: [f32; 2],
+    /// Set `uniform.max_iterations` from the zoom depth on every update.
+    max_iterations_auto: bool,
     //
+    pub viewport: Viewport,
+    /// Filled in from `viewport` in `update`, right before being uploaded.
     pub uniform: Uniform,
-    uniform_buffer: wgpu::Buffer,
-    uniform_bind_group: wgpu::BindGroup,
+    /// `c` of the last scene shown, exactly, see `Scene::get_c`.
+    c: [f64; 2],
+    /// Formula of the custom fractal kinds, compiled into the pipeline by `set_formula`.
+    formula: Expression,
+    /// Built-in palettes followed by any loaded ones, `palette_index` is the one shown.
+    palettes: Vec<Palette>,
+    palette_index: usize,
+    /// Shift `uniform.palette_offset` with time.
+    pub palette_cycling: bool,
+    /// Samples averaged in every pixel while the view stays still.
+    pub sampling: Sampling,
+    /// Keep averaging samples past those of `sampling`, up to `ACCUMULATION_SAMPLES`,
+    /// so that still views converge.
+    pub accumulating: bool,
+    /// Stage the next frame is rendered at, see `update`.
+    refinement: Refinement,
+    /// `uniform` of the last update as from `get_refinement_key`, to tell what changed
+    /// since. `None` starts over from a preview, as after a resize or
+    /// when the palette or formula changed.
+    refinement_key: Option<Uniform>,
+    /// The view was too deep for the fractal at the last update, to warn once on the way in.
+    beyond_precision: bool,
 }
 
 impl State {
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new(window: &Window) -> Result<Self, StateError> {
         let size = window.inner_size();
         let c_from_mouse = false;
         // The instance is a handle to our GPU
@@ -40,215 +135,144 @@ impl State {
             flags: wgpu::InstanceFlags::default(),
             gles_minor_version: Gles3MinorVersion::default(),
         });
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
+        let palettes = Palette::library();
+        let gpu =
+            match unsafe { instance.create_surface(&window) } {
+                Ok(surface) => request_device(&instance, Some(&surface)).await.map(
+                    |(adapter, device, queue)| {
+                        Gpu::new(surface, &adapter, device, queue, size, &palettes[0])
                     },
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
-
-        let surface_caps = surface.get_capabilities(&adapter);
-
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            // .filter(|f| f.describe().srgb)
-            .next()
-            .unwrap_or(surface_caps.formats[0]);
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
+                ),
+                Err(_) => None,
+            };
+        let backend = match gpu {
+            Some(gpu) => Backend::Gpu(Box::new(gpu)),
+            None => Backend::cpu(window)?,
         };
-        surface.configure(&device, &config);
-
-        let uniform = Uniform::default();
 
-        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        let uniform_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("uniform_bind_group_layout"),
-            });
-
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
-            }],
-            label: Some("uniform_bind_group"),
-        });
-
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader.wgsl").into()),
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&uniform_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE
-                // or Features::POLYGON_MODE_POINT
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            // If the pipeline will be used with a multiview render pass, this
-            // indicates how many array layers the attachments will have.
-            multiview: None,
-        });
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        let num_indices = INDICES.len() as u32;
         let instant = Instant::now();
         let dragging = false;
         let dragging_position_original = [0.0, 0.0];
 
-        Self {
-            surface,
-            device,
-            queue,
-            config,
+        let mut state = Self {
+            backend,
             size,
             c_from_mouse,
+            power_from_mouse: false,
+            trap_from_mouse: false,
             dragging,
             dragging_position_original,
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
+            max_iterations_auto: true,
             instant,
-            uniform,
-            uniform_buffer,
-            uniform_bind_group,
-        }
+            viewport: Viewport::default(),
+            uniform: Uniform::default(),
+            c: Uniform::default().get_c(),
+            formula: Expression::default(),
+            palettes,
+            palette_index: 0,
+            palette_cycling: true,
+            sampling: Sampling::default(),
+            accumulating: true,
+            refinement: Refinement::Preview,
+            refinement_key: None,
+            beyond_precision: false,
+        };
+        state.resize(size);
+        Ok(state)
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-
-            let domain_size = self.uniform.get_domain_size();
-
-            let ratio_window = self.size.width as f32 / self.size.height as f32;
-
-            let domain_size_target_y = domain_size[0] / ratio_window;
-            let domain_size_delta_y = domain_size[1] - domain_size_target_y;
+            self.refinement_key = None;
+            match &mut self.backend {
+                Backend::Gpu(gpu) => {
+                    gpu.config.width = new_size.width;
+                    gpu.config.height = new_size.height;
+                    gpu.surface.configure(&gpu.device, &gpu.config);
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                Backend::Cpu { surface, .. } => {
+                    let (width, height) = (
+                        NonZeroU32::new(new_size.width).unwrap(),
+                        NonZeroU32::new(new_size.height).unwrap(),
+                    );
+                    if let Err(error) = surface.resize(width, height) {
+                        log::error!("could not resize the window buffer: {}", error);
+                    }
+                }
+            }
 
-            self.uniform.domain[1] = [
-                self.uniform.domain[1][0] + domain_size_delta_y / 2.0,
-                self.uniform.domain[1][1] - domain_size_delta_y / 2.0,
-            ];
+            self.viewport
+                .set_aspect_ratio(new_size.width as f64 / new_size.height as f64);
         }
     }
 
     pub fn reset_zoom(&mut self) {
-        self.uniform.domain = Uniform::default().domain;
+        self.viewport = Viewport::default();
         self.resize(self.size);
 
-        let size_y = self.uniform.domain[1][1] - self.uniform.domain[1][0];
-        let size_y_min = (Uniform::default().domain[1][1] - Uniform::default().domain[1][0]) * 0.75;
+        let size_y = self.viewport.get_size()[1];
+        let size_y_min = Viewport::default().get_size()[1] * 0.75;
         if size_y < size_y_min {
-            let ratio = size_y_min / size_y;
-            self.uniform.domain[1] = [-size_y_min / 2.0, size_y_min / 2.0];
-            self.uniform.domain[0] = [
-                self.uniform.domain[0][0] * ratio,
-                self.uniform.domain[0][1] * ratio,
-            ];
+            self.viewport.scale *= size_y_min / size_y;
         }
     }
 
+    /// Show `scene`, keeping the shape of the window and the input state.
+    pub fn set_scene(&mut self, scene: Scene) {
+        self.viewport = scene.viewport;
+        self.resize(self.size);
+        self.uniform = Uniform {
+            mouse: self.uniform.mouse,
+            time: self.uniform.time,
+            ..scene.uniform
+        };
+        self.c = scene.c;
+        self.set_formula(scene.formula);
+        self.max_iterations_auto = scene.max_iterations_auto;
+        self.c_from_mouse = false;
+        self.power_from_mouse = false;
+        self.trap_from_mouse = false;
+
+        let index = match self
+            .palettes
+            .iter()
+            .position(|palette| *palette == scene.palette)
+        {
+            Some(index) => index,
+            None => {
+                self.palettes.push(scene.palette);
+                self.palettes.len() - 1
+            }
+        };
+        self.set_palette_index(index as isize);
+    }
+
+    pub fn get_scene(&self) -> Scene {
+        Scene {
+            viewport: self.viewport.clone(),
+            uniform: self.uniform,
+            formula: self.formula.clone(),
+            palette: self.palettes[self.palette_index].clone(),
+            max_iterations_auto: self.max_iterations_auto,
+            c: self.c,
+        }
+    }
+
+    /// Load a parameter file and show it, with the palette standing still as it was saved.
+    pub fn load_scene(&mut self, path: &std::path::Path) -> Result<(), SceneError> {
+        let scene = Scene::load(path)?;
+        self.set_scene(scene);
+        self.palette_cycling = false;
+        Ok(())
+    }
+
+    pub fn save_scene(&self, path: &std::path::Path) -> Result<(), SceneError> {
+        self.get_scene().save(path)
+    }
+
+    /// Whether `event` changed what is drawn, with `uniform.mouse` already following the cursor.
     pub fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -279,7 +303,9 @@ impl State {
                 ..
             } => {
                 self.c_from_mouse = false;
-                self.uniform.zoom_in();
+                self.power_from_mouse = false;
+                self.trap_from_mouse = false;
+                self.viewport.zoom_in(self.get_mouse());
                 true
             }
             WindowEvent::KeyboardInput {
@@ -301,7 +327,9 @@ impl State {
                 ..
             } => {
                 self.c_from_mouse = false;
-                self.uniform.zoom_out();
+                self.power_from_mouse = false;
+                self.trap_from_mouse = false;
+                self.viewport.zoom_out(self.get_mouse());
                 true
             }
             WindowEvent::KeyboardInput {
@@ -318,17 +346,17 @@ impl State {
                 button: MouseButton::Left,
                 ..
             } => {
-                if self.uniform.mandelbrot == 1 {
+                if !self.uniform.get_fractal_kind().is_julia() {
                     self.c_from_mouse = false;
                     return true;
                 }
 
                 self.c_from_mouse = !self.c_from_mouse;
+                self.power_from_mouse = false;
+                self.trap_from_mouse = false;
 
                 // reset zoom
-                if (self.uniform.domain[0][1] - self.uniform.domain[0][0])
-                    < (Uniform::default().domain[0][1] - Uniform::default().domain[0][0])
-                {
+                if self.viewport.scale < Viewport::default().scale {
                     // do not reset when zoomed out
                     self.reset_zoom();
                 }
@@ -337,12 +365,33 @@ impl State {
             }
             WindowEvent::CursorMoved { .. } => {
                 if self.c_from_mouse && !self.dragging {
-                    self.uniform.c = [
-                        (self.uniform.mouse[0] as f32 - 0.5) * 2.0,
-                        (self.uniform.mouse[1] as f32 - 0.5) * 2.0,
+                    self.uniform.set_c([
+                        (self.uniform.mouse[0] as f64 - 0.5) * 2.0,
+                        (self.uniform.mouse[1] as f64 - 0.5) * 2.0,
+                    ]);
+                }
+                let newton = self.uniform.get_fractal_kind().get_formula() == Formula::Newton;
+                if self.power_from_mouse && !self.dragging && newton {
+                    // real part from 0 to 2 across the window, imaginary part from -1 to 1
+                    self.uniform.relaxation = [
+                        self.uniform.mouse[0] * 2.0,
+                        (self.uniform.mouse[1] - 0.5) * 2.0,
+                    ];
+                } else if self.power_from_mouse && !self.dragging {
+                    // real part from 1 to 9 across the window, imaginary part from -1 to 1
+                    self.uniform.power = [
+                        1.0 + self.uniform.mouse[0] * 8.0,
+                        (self.uniform.mouse[1] - 0.5) * 2.0,
                     ];
                 }
-                true
+                if self.trap_from_mouse && !self.dragging {
+                    // same range as c
+                    self.uniform.trap_position = [
+                        (self.uniform.mouse[0] - 0.5) * 2.0,
+                        (self.uniform.mouse[1] - 0.5) * 2.0,
+                    ];
+                }
+                self.dragging || self.c_from_mouse || self.power_from_mouse || self.trap_from_mouse
             }
             WindowEvent::MouseWheel { delta, .. } => {
                 let y: f32 = match delta {
@@ -358,9 +407,9 @@ impl State {
                     let zoom_in: bool = y > 0.0;
                     for _ in 0..zoom_many_times {
                         if zoom_in {
-                            self.uniform.zoom_in();
+                            self.viewport.zoom_in(self.get_mouse());
                         } else {
-                            self.uniform.zoom_out();
+                            self.viewport.zoom_out(self.get_mouse());
                         }
                     }
                 }
@@ -412,14 +461,145 @@ impl State {
                     },
                 ..
             } => {
-                self.uniform.mandelbrot = 1;
-                self.c_from_mouse = false;
-                self.uniform.c = [0.0, 0.0];
-                self.reset_zoom();
-                // center the mandelbrot a bit
-                let shift = 0.6;
-                self.uniform.domain[0][0] -= shift;
-                self.uniform.domain[0][1] -= shift;
+                let formula = self.uniform.get_fractal_kind().get_formula();
+                self.set_fractal_kind(FractalKind::new(formula, false));
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::PageUp),
+                        ..
+                    },
+                ..
+            } => {
+                self.max_iterations_auto = false;
+                self.uniform.max_iterations =
+                    (self.uniform.max_iterations * 2).min(MAX_ITERATIONS_LIMIT);
+                log::info!("max iterations: {}", self.uniform.max_iterations);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::PageDown),
+                        ..
+                    },
+                ..
+            } => {
+                self.max_iterations_auto = false;
+                self.uniform.max_iterations =
+                    (self.uniform.max_iterations / 2).max(MAX_ITERATIONS_MIN);
+                log::info!("max iterations: {}", self.uniform.max_iterations);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::A),
+                        ..
+                    },
+                ..
+            } => {
+                self.max_iterations_auto = !self.max_iterations_auto;
+                log::info!("automatic max iterations: {}", self.max_iterations_auto);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::B),
+                        ..
+                    },
+                ..
+            } => {
+                self.uniform.coloring_mode = match self.uniform.coloring_mode {
+                    COLORING_BANDED => COLORING_SMOOTH,
+                    COLORING_SMOOTH => COLORING_DISTANCE,
+                    COLORING_DISTANCE => COLORING_TRAP,
+                    COLORING_TRAP => COLORING_STRIPE,
+                    COLORING_STRIPE => COLORING_TRIANGLE,
+                    COLORING_TRIANGLE => COLORING_HISTOGRAM,
+                    _ => COLORING_BANDED,
+                };
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        ..
+                    },
+                ..
+            } => {
+                self.set_palette_index(self.palette_index as isize + 1);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::O),
+                        ..
+                    },
+                ..
+            } => {
+                self.set_palette_index(self.palette_index as isize - 1);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::T),
+                        ..
+                    },
+                ..
+            } => {
+                self.palette_cycling = !self.palette_cycling;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::N),
+                        ..
+                    },
+                ..
+            } => {
+                self.accumulating = !self.accumulating;
+                self.refinement_key = None;
+                log::info!("accumulating: {}", self.accumulating);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Q),
+                        ..
+                    },
+                ..
+            } => {
+                self.viewport.rotate(ROTATION_STEP);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::E),
+                        ..
+                    },
+                ..
+            } => {
+                self.viewport.rotate(-ROTATION_STEP);
                 true
             }
             WindowEvent::KeyboardInput {
@@ -431,35 +611,475 @@ impl State {
                     },
                 ..
             } => {
-                self.uniform.mandelbrot = 0;
+                let formula = self.uniform.get_fractal_kind().get_formula();
+                self.set_fractal_kind(FractalKind::new(formula, true));
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode:
+                            Some(
+                                key @ (VirtualKeyCode::Key1
+                                | VirtualKeyCode::Key2
+                                | VirtualKeyCode::Key3
+                                | VirtualKeyCode::Key4
+                                | VirtualKeyCode::Key5
+                                | VirtualKeyCode::Key6
+                                | VirtualKeyCode::Key7
+                                | VirtualKeyCode::Key8),
+                            ),
+                        ..
+                    },
+                ..
+            } => {
+                let formula = match key {
+                    VirtualKeyCode::Key1 => Formula::Mandelbrot,
+                    VirtualKeyCode::Key2 => Formula::BurningShip,
+                    VirtualKeyCode::Key3 => Formula::Tricorn,
+                    VirtualKeyCode::Key4 => Formula::Celtic,
+                    VirtualKeyCode::Key5 => Formula::Perpendicular,
+                    VirtualKeyCode::Key6 => Formula::Buffalo,
+                    VirtualKeyCode::Key7 => Formula::Newton,
+                    _ => Formula::Custom,
+                };
+                let julia = self.uniform.get_fractal_kind().is_julia();
+                self.set_fractal_kind(FractalKind::new(formula, julia));
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::D),
+                        ..
+                    },
+                ..
+            } => {
+                self.power_from_mouse = !self.power_from_mouse;
                 self.c_from_mouse = false;
-                self.reset_zoom();
-                self.uniform.c = Uniform::default().c;
+                self.trap_from_mouse = false;
+                if !self.power_from_mouse {
+                    if self.uniform.get_fractal_kind().get_formula() == Formula::Newton {
+                        log::info!("relaxation: {:?}", self.uniform.relaxation);
+                    } else {
+                        log::info!("power: {:?}", self.uniform.power);
+                    }
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::I),
+                        ..
+                    },
+                ..
+            } => {
+                let (interior_mode, name) = match self.uniform.interior_mode {
+                    INTERIOR_BLACK => (INTERIOR_PERIOD, "period"),
+                    INTERIOR_PERIOD => (INTERIOR_MULTIPLIER, "multiplier"),
+                    INTERIOR_MULTIPLIER => (INTERIOR_FINAL_Z, "final |z|"),
+                    _ => (INTERIOR_BLACK, "black"),
+                };
+                self.uniform.interior_mode = interior_mode;
+                log::info!("interior: {}", name);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::X),
+                        ..
+                    },
+                ..
+            } => {
+                self.trap_from_mouse = !self.trap_from_mouse;
+                self.c_from_mouse = false;
+                self.power_from_mouse = false;
+                self.uniform.coloring_mode = COLORING_TRAP;
+                if !self.trap_from_mouse {
+                    log::info!("orbit trap position: {:?}", self.uniform.trap_position);
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::G),
+                        ..
+                    },
+                ..
+            } => {
+                // the next shape, starting from the current one when not coloured by the trap
+                if self.uniform.coloring_mode == COLORING_TRAP {
+                    self.uniform.trap_shape = match self.uniform.trap_shape {
+                        TRAP_POINT => TRAP_LINE,
+                        TRAP_LINE => TRAP_CROSS,
+                        TRAP_CROSS => TRAP_CIRCLE,
+                        _ => TRAP_POINT,
+                    };
+                }
+                self.uniform.coloring_mode = COLORING_TRAP;
+                let shape = match self.uniform.trap_shape {
+                    TRAP_LINE => "line",
+                    TRAP_CROSS => "cross",
+                    TRAP_CIRCLE => "circle",
+                    _ => "point",
+                };
+                log::info!("orbit trap: {}", shape);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::H),
+                        ..
+                    },
+                ..
+            } => {
+                self.uniform.newton_method = if self.uniform.newton_method == NEWTON_METHOD_HALLEY {
+                    log::info!("Newton's method");
+                    NEWTON_METHOD_NEWTON
+                } else {
+                    log::info!("Halley's method");
+                    NEWTON_METHOD_HALLEY
+                };
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::LBracket),
+                        ..
+                    },
+                ..
+            } => {
+                // to the next whole power, where all precision modes are available
+                let power = (self.uniform.power[0].ceil() - 1.0).max(2.0);
+                self.set_power([power, 0.0]);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::RBracket),
+                        ..
+                    },
+                ..
+            } => {
+                let power = (self.uniform.power[0].floor() + 1.0).min(POWER_INTEGER_MAX as f32);
+                self.set_power([power, 0.0]);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Comma),
+                        ..
+                    },
+                ..
+            } => {
+                // to the next whole density, where the stripes have no seam
+                let density = (self.uniform.stripe_density.ceil() - 1.0).max(1.0);
+                self.uniform.stripe_density = density;
+                self.uniform.coloring_mode = COLORING_STRIPE;
+                log::info!("stripe density: {}", density);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Period),
+                        ..
+                    },
+                ..
+            } => {
+                let density = self.uniform.stripe_density.floor() + 1.0;
+                self.uniform.stripe_density = density;
+                self.uniform.coloring_mode = COLORING_STRIPE;
+                log::info!("stripe density: {}", density);
+                true
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::S),
+                        ..
+                    },
+                ..
+            } => {
+                let path = std::path::Path::new(SCENE_FILE_NAME);
+                // the full path, as the working directory is not always obvious
+                let written = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+                match self.save_scene(path) {
+                    Ok(()) => log::info!("saved view to {}", written.display()),
+                    Err(error) => log::error!("could not save {}: {}", written.display(), error),
+                }
+                true
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::L),
+                        ..
+                    },
+                ..
+            } => {
+                let path = std::path::Path::new(SCENE_FILE_NAME);
+                if let Err(error) = self.load_scene(path) {
+                    log::error!("could not load {}: {}", path.display(), error);
+                }
                 true
             }
             _ => false,
-        };
-        false
+        }
+    }
+
+    /// Whether frames differ without any input, while the palette cycles, the view is dragged
+    /// or the frame is still being refined. Once all samples are averaged and the palette
+    /// stands still, frames only repeat the average.
+    pub fn is_animating(&self) -> bool {
+        self.palette_cycling || self.dragging || self.refinement != Refinement::Finished
     }
 
     pub fn update(&mut self) {
         self.uniform.time = self.instant.elapsed().as_secs_f32();
+        if self.palette_cycling {
+            self.uniform.palette_offset = (self.uniform.time / PALETTE_CYCLE_PERIOD) % 1.0;
+        }
         if self.dragging {
-            self.uniform.translate([
-                self.uniform.mouse[0] - self.dragging_position_original[0],
-                self.uniform.mouse[1] - self.dragging_position_original[1],
+            let mouse = self.get_mouse();
+            self.viewport.translate([
+                mouse[0] - self.dragging_position_original[0] as f64,
+                mouse[1] - self.dragging_position_original[1] as f64,
             ]);
             self.dragging_position_original = self.uniform.mouse;
         }
 
-        self.queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            bytemuck::cast_slice(&[self.uniform]),
-        );
+        self.viewport.write_uniform(&mut self.uniform);
+        let beyond_precision = self.viewport.is_beyond_precision(&self.uniform);
+        if beyond_precision && !self.beyond_precision {
+            log::warn!("{}", PRECISION_WARNING);
+        }
+        self.beyond_precision = beyond_precision;
+        if self.max_iterations_auto {
+            self.uniform.max_iterations = self.viewport.get_max_iterations_auto();
+        }
+
+        let key = get_refinement_key(&self.uniform);
+        self.refinement =
+            self.refinement
+                .advance(self.refinement_key.as_ref(), &key, self.get_samples());
+        self.refinement_key = Some(key);
+        self.uniform.sample_offset = self
+            .refinement
+            .get_sample_offset(&self.sampling, self.uniform.sample_offset);
+
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            gpu.renderer
+                .prepare(&gpu.device, &gpu.queue, &self.viewport, &mut self.uniform);
+        }
+    }
+
+    /// Samples averaged in every pixel before frames are finished.
+    fn get_samples(&self) -> u32 {
+        let samples = self.sampling.get_samples();
+        match &self.backend {
+            Backend::Gpu(gpu) if self.accumulating && gpu.blit.is_accumulation_float() => {
+                samples.max(ACCUMULATION_SAMPLES)
+            }
+            // an average of bytes stops improving early, and the CPU would be kept busy for long
+            _ => samples,
+        }
+    }
+
+    /// Show `fractal_kind` from its first view, Julia sets start from the default `c`
+    /// and parameter planes from `z = 0`.
+    fn set_fractal_kind(&mut self, fractal_kind: FractalKind) {
+        self.uniform.set_fractal_kind(fractal_kind);
+        self.c_from_mouse = false;
+        self.reset_zoom();
+        self.uniform.reset_c(fractal_kind);
+        if !fractal_kind.is_julia() {
+            self.viewport
+                .set_center(fractal_kind.get_formula().get_center());
+        }
+        log::info!("fractal: {:?}", fractal_kind);
+    }
+
+    /// Rebuild the pipeline with `formula`, keeping the current one if it does not compile.
+    pub fn set_formula(&mut self, formula: Expression) {
+        if formula == self.formula {
+            return;
+        }
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            if let Err(error) = gpu.renderer.set_formula(&gpu.device, &formula) {
+                log::error!("could not use the formula {}: {}", formula, error);
+                return;
+            }
+        }
+        self.uniform.set_formula(&formula);
+        self.formula = formula;
+        self.refinement_key = None;
+        log::info!("formula: {}", self.formula);
+    }
+
+    fn set_power(&mut self, power: [f32; 2]) {
+        self.uniform.power = power;
+        self.power_from_mouse = false;
+        log::info!("power: {:?}", power);
+    }
+
+    /// Show the palette at `index` in the list, wrapping around at both ends.
+    fn set_palette_index(&mut self, index: isize) {
+        self.palette_index = index.rem_euclid(self.palettes.len() as isize) as usize;
+        let palette = &self.palettes[self.palette_index];
+        if let Backend::Gpu(gpu) = &self.backend {
+            gpu.renderer.set_palette(&gpu.queue, palette);
+        }
+        self.refinement_key = None;
+        log::info!("palette: {}", palette.name);
+    }
+
+    /// Load a palette file (`.ggr`, `.map` or `.json`) and show it.
+    pub fn load_palette(&mut self, path: &std::path::Path) -> Result<(), PaletteError> {
+        let palette = Palette::load(path)?;
+        self.palettes.push(palette);
+        self.set_palette_index(self.palettes.len() as isize - 1);
+        Ok(())
+    }
+
+    /// Mouse position from 0.0 to 1.0.
+    fn get_mouse(&self) -> [f64; 2] {
+        [self.uniform.mouse[0] as f64, self.uniform.mouse[1] as f64]
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let weight = self.refinement.get_weight();
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.render(self.refinement, weight),
+            #[cfg(not(target_arch = "wasm32"))]
+            Backend::Cpu {
+                surface, average, ..
+            } => {
+                let (width, height) = (self.size.width, self.size.height);
+                if self.refinement != Refinement::Finished {
+                    let [render_width, render_height] = self.refinement.get_size(width, height);
+                    let mut image = cpu::render(
+                        &self.viewport,
+                        &self.uniform,
+                        &self.formula,
+                        &self.palettes[self.palette_index],
+                        render_width,
+                        render_height,
+                    );
+                    if [render_width, render_height] != [width, height] {
+                        image =
+                            image::imageops::resize(&image, width, height, FilterType::Triangle);
+                    }
+                    average.resize((width * height) as usize, [0.0; 3]);
+                    for (color, rgba) in average.iter_mut().zip(image.pixels()) {
+                        for (channel, value) in color.iter_mut().zip(rgba.0) {
+                            *channel += (value as f32 - *channel) * weight;
+                        }
+                    }
+                }
+                let result = surface.buffer_mut().and_then(|mut buffer| {
+                    // softbuffer pixels are 0RGB
+                    for (pixel, color) in buffer.iter_mut().zip(average.iter()) {
+                        let [r, g, b] = color.map(|channel| channel.round() as u32);
+                        *pixel = r << 16 | g << 8 | b;
+                    }
+                    buffer.present()
+                });
+                if let Err(error) = result {
+                    log::error!("could not draw to the window: {}", error);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Backend {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn cpu(window: &Window) -> Result<Self, StateError> {
+        log::warn!("no usable graphics adapter, rendering on the CPU");
+        let context = unsafe { softbuffer::Context::new(window) }.map_err(StateError::Software)?;
+        let surface =
+            unsafe { softbuffer::Surface::new(&context, window) }.map_err(StateError::Software)?;
+        Ok(Backend::Cpu {
+            _context: context,
+            surface,
+            average: Vec::new(),
+        })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn cpu(_window: &Window) -> Result<Self, StateError> {
+        Err(StateError::NoAdapter)
+    }
+}
+
+impl Gpu {
+    fn new(
+        surface: wgpu::Surface,
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        size: winit::dpi::PhysicalSize<u32>,
+        palette: &Palette,
+    ) -> Self {
+        let surface_caps = surface.get_capabilities(adapter);
+
+        let surface_format = surface_caps
+            .formats
+            .iter()
+            .copied()
+            // palette colours are already sRGB encoded
+            .find(|f| !f.is_srgb())
+            .unwrap_or(surface_caps.formats[0]);
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format,
+            width: size.width,
+            height: size.height,
+            present_mode: surface_caps.present_modes[0],
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+        };
+        surface.configure(&device, &config);
+
+        let renderer = Renderer::new(&device, &queue, config.format, palette);
+        let blit = Blit::new(&device, adapter, config.format);
+
+        Self {
+            surface,
+            device,
+            queue,
+            config,
+            renderer,
+            blit,
+        }
+    }
+
+    /// Draw a frame as far refined as `refinement`, blending its sample in with `weight`.
+    fn render(&mut self, refinement: Refinement, weight: f32) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -471,30 +1091,19 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            render_pass.set_pipeline(&self.render_pipeline);
-
-            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+        let [width, height] = refinement.get_size(self.config.width, self.config.height);
+        if refinement != Refinement::Finished {
+            let target = self.blit.get_target(&self.device, [width, height]);
+            self.renderer
+                .render(&self.device, &mut encoder, target, width, height);
+        }
+        match refinement {
+            Refinement::Preview | Refinement::Cycling => self.blit.draw(&mut encoder, &view),
+            Refinement::Sample(_) => {
+                self.blit.accumulate(&self.device, &mut encoder, weight);
+                self.blit.draw_accumulation(&mut encoder, &view);
+            }
+            Refinement::Finished => self.blit.draw_accumulation(&mut encoder, &view),
         }
 
         self.queue.submit(iter::once(encoder.finish()));
@@ -1,95 +1,240 @@
 use bytemuck::{Pod, Zeroable};
 
-#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+use crate::expression::Expression;
+use crate::fractal::{Formula, FractalKind};
+use crate::polynomial::{Polynomial, POLYNOMIAL_DEGREE_MAX};
+
+/// Values of `Uniform::precision_mode`, the arithmetic used by the shader.
+pub const PRECISION_SINGLE: i32 = 0;
+pub const PRECISION_DOUBLE_SINGLE: i32 = 1;
+pub const PRECISION_PERTURBATION: i32 = 2;
+
+/// Iterations before a point is considered not to escape, unless set automatically.
+pub const MAX_ITERATIONS_DEFAULT: u32 = 256;
+pub const MAX_ITERATIONS_MIN: u32 = 16;
+/// Keeps the reference orbit within the largest texture WebGL2 allows.
+pub const MAX_ITERATIONS_LIMIT: u32 = 1 << 20;
+
+/// Values of `Uniform::coloring_mode`, how the escape iteration is turned into a colour.
+pub const COLORING_BANDED: i32 = 0;
+pub const COLORING_SMOOTH: i32 = 1;
+pub const COLORING_DISTANCE: i32 = 2;
+pub const COLORING_TRAP: i32 = 3;
+/// Averages along the orbit, see `add_average_term` in `shader.wgsl`.
+pub const COLORING_STRIPE: i32 = 4;
+pub const COLORING_TRIANGLE: i32 = 5;
+/// Smooth iteration counts spread evenly over the palette, see `histogram.wgsl`.
+pub const COLORING_HISTOGRAM: i32 = 6;
+
+/// Values of `Uniform::trap_shape`, what the orbit trap measures the distance to.
+pub const TRAP_POINT: i32 = 0;
+/// Horizontal line through `trap_position`.
+pub const TRAP_LINE: i32 = 1;
+/// Horizontal and vertical lines through `trap_position`.
+pub const TRAP_CROSS: i32 = 2;
+/// Circle of `trap_radius` around `trap_position`.
+pub const TRAP_CIRCLE: i32 = 3;
+
+/// Values of `Uniform::interior_mode`, how points that do not escape are coloured.
+pub const INTERIOR_BLACK: i32 = 0;
+/// By the period of the cycle the orbit is attracted to.
+pub const INTERIOR_PERIOD: i32 = 1;
+/// By the angle and magnitude of the multiplier of that cycle.
+pub const INTERIOR_MULTIPLIER: i32 = 2;
+/// By `|z|` at the last iteration.
+pub const INTERIOR_FINAL_Z: i32 = 3;
+
+/// Largest power iterated by complex multiplication, and so with all precision modes.
+/// Other powers go through the polar form in single precision. Must match `shader.wgsl`.
+pub const POWER_INTEGER_MAX: u32 = 16;
+
+/// Values of `Uniform::newton_method`, the root finding step of the Newton fractals.
+pub const NEWTON_METHOD_NEWTON: i32 = 0;
+pub const NEWTON_METHOD_HALLEY: i32 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Zeroable, Pod)]
 #[repr(C)]
 pub struct Uniform {
-    pub domain: [[f32; 2]; 2],
+    /// Size of the view in units of `2^exponent`, see `Viewport::write_uniform`.
+    pub size: [f32; 2],
+    /// Cosine and sine of the rotation of the view.
+    pub rotation: [f32; 2],
     pub mouse: [f32; 2],
+    /// `c` is `c + c_lo`, use `set_c` to keep both parts consistent.
     pub c: [f32; 2],
+    pub c_lo: [f32; 2],
+    /// `center + center_lo` approximates the exact center kept by the `Viewport`.
+    pub center: [f32; 2],
+    pub center_lo: [f32; 2],
+    /// Exponent `d` the formulas take in place of the square, `z^d + c` for the Mandelbrot
+    /// formula. Complex in general, see `get_integer_power`.
+    pub power: [f32; 2],
+    /// Factor `a` of the Newton step, `z -> z - a p(z) / p'(z) + c`, 1 for Newton's method.
+    pub relaxation: [f32; 2],
+    /// Point the orbit trap is placed at, in the plane of `z`.
+    pub trap_position: [f32; 2],
+    /// Offset of the sample from the center of the pixel, in pixels with y pointing down,
+    /// see `Sampling::get_offset`.
+    pub sample_offset: [f32; 2],
     pub time: f32,
-    pub mandelbrot: i32,
-    pub _padding: [f32; 2],
+    /// A `FractalKind`, use `get_fractal_kind` and `set_fractal_kind`.
+    pub fractal_kind: i32,
+    pub exponent: i32,
+    pub precision_mode: i32,
+    pub reference_length: u32,
+    /// Always `1.0`, see the double-single functions in `shader.wgsl`.
+    pub one: f32,
+    pub max_iterations: u32,
+    pub coloring_mode: i32,
+    /// Iterations it takes to go once through the palette.
+    pub palette_period: f32,
+    /// Shift of the palette, from 0.0 to 1.0.
+    pub palette_offset: f32,
+    /// Degree of `polynomial`.
+    pub degree: u32,
+    pub newton_method: i32,
+    /// Power smooth colouring takes for the custom formula, see `Expression::get_degree`.
+    pub formula_degree: f32,
+    /// Shape of the orbit trap that `COLORING_TRAP` colours by the distance to.
+    pub trap_shape: i32,
+    pub trap_radius: f32,
+    pub interior_mode: i32,
+    /// Stripes per turn around the origin of `COLORING_STRIPE`, whole numbers have no seam.
+    pub stripe_density: f32,
+    /// Keeps `polynomial` at the 16 byte alignment WGSL gives arrays in uniforms.
+    pub _padding: [u32; 1],
+    /// Polynomial of the Newton fractals, the coefficient of `z^k` in `xy` and the
+    /// `k`-th root in `zw` of element `k`. Use `set_polynomial` and `get_polynomial`.
+    pub polynomial: [[f32; 4]; POLYNOMIAL_DEGREE_MAX + 1],
 }
 
-impl Uniform {
-    pub fn default() -> Self {
+impl Default for Uniform {
+    fn default() -> Self {
         let mouse = [0.0, 0.0];
         let time = 0.0;
-        let domain = [[-1.55, 1.55], [-1.55, 1.55]];
         let c = [-0.75, 0.0];
-        let mandelbrot = 0; // 0 is false, > 0 is true (cannot use bool)
-        Self {
+        let mut uniform = Self {
             mouse,
             time,
-            domain,
+            size: [3.1, 3.1],
+            rotation: [1.0, 0.0],
             c,
-            mandelbrot,
-            _padding: [0.0, 0.0],
-        }
+            c_lo: [0.0, 0.0],
+            center: [0.0, 0.0],
+            center_lo: [0.0, 0.0],
+            power: [2.0, 0.0],
+            relaxation: [1.0, 0.0],
+            trap_position: [0.0, 0.0],
+            sample_offset: [0.0, 0.0],
+            fractal_kind: FractalKind::Julia as i32,
+            exponent: 0,
+            precision_mode: PRECISION_SINGLE,
+            reference_length: 0,
+            one: 1.0,
+            max_iterations: MAX_ITERATIONS_DEFAULT,
+            coloring_mode: COLORING_SMOOTH,
+            palette_period: 64.0,
+            palette_offset: 0.0,
+            degree: 0,
+            newton_method: NEWTON_METHOD_NEWTON,
+            formula_degree: 2.0,
+            trap_shape: TRAP_POINT,
+            trap_radius: 0.5,
+            interior_mode: INTERIOR_BLACK,
+            stripe_density: 4.0,
+            _padding: [0; 1],
+            polynomial: [[0.0; 4]; POLYNOMIAL_DEGREE_MAX + 1],
+        };
+        uniform.set_polynomial(&Polynomial::default());
+        uniform
     }
+}
 
-    fn zoom(&mut self, zoom_in: bool) {
-        let domain_size = self.get_domain_size();
-        if zoom_in {
-            // limit zoom in due to precision
-            let domain_size_min = domain_size[0].min(domain_size[1]);
-            if domain_size_min <= 0.00001 {
-                return;
-            }
-        } else {
-            // do not allow arbitrary zoom out
-            let domain_size_max = domain_size[0].max(domain_size[1]);
-            if domain_size_max >= 20.0 {
-                return;
-            }
-        }
+impl Uniform {
+    pub fn get_fractal_kind(&self) -> FractalKind {
+        FractalKind::from_i32(self.fractal_kind).unwrap_or(FractalKind::Julia)
+    }
+
+    pub fn set_fractal_kind(&mut self, fractal_kind: FractalKind) {
+        self.fractal_kind = fractal_kind as i32;
+    }
 
-        let zoom_factor: f32 = 0.025;
-        let r: f32 = if zoom_in {
-            1.0 - zoom_factor
-        } else {
-            1.0 + zoom_factor
+    /// Set `c` for the first view of `fractal_kind`: the default Julia set parameter, `z = 0`
+    /// to start parameter planes from, no constant term for Newton's method and the first
+    /// root of the polynomial to start the Nova plane from.
+    pub fn reset_c(&mut self, fractal_kind: FractalKind) {
+        let c = match (fractal_kind.get_formula(), fractal_kind.is_julia()) {
+            (Formula::Newton, true) => [0.0, 0.0],
+            (Formula::Newton, false) => {
+                let [_, _, re, im] = self.polynomial[0];
+                [re as f64, im as f64]
+            }
+            (_, true) => Uniform::default().get_c(),
+            (_, false) => [0.0, 0.0],
         };
-        let mouse = self.mouse; // from 0.0 to 1.0
-
-        self.domain = [
-            [
-                self.domain[0][0] + (1.0 - r) * domain_size[0] * mouse[0],
-                self.domain[0][1] - (1.0 - r) * domain_size[0] * (1.0 - mouse[0]),
-            ],
-            [
-                self.domain[1][0] + (1.0 - r) * domain_size[1] * (1.0 - mouse[1]),
-                self.domain[1][1] - (1.0 - r) * domain_size[1] * mouse[1],
-            ],
-        ];
+        self.set_c(c);
     }
 
-    pub fn zoom_in(&mut self) {
-        self.zoom(true);
+    /// Only single precision draws powers that are not whole numbers, the Newton fractals
+    /// and custom formulas, deeper views are drawn in it anyway, see
+    /// `Viewport::is_beyond_precision`.
+    pub fn is_single_precision_only(&self) -> bool {
+        let formula = self.get_fractal_kind().get_formula();
+        self.get_integer_power().is_none() || matches!(formula, Formula::Newton | Formula::Custom)
     }
 
-    pub fn zoom_out(&mut self) {
-        self.zoom(false);
+    /// Keep the fields that follow from the custom formula, which is compiled into the shader.
+    pub fn set_formula(&mut self, formula: &Expression) {
+        self.formula_degree = formula.get_degree().unwrap_or(0.0) as f32;
     }
 
-    pub fn get_domain_size(&self) -> [f32; 2] {
-        return [
-            self.domain[0][1] - self.domain[0][0],
-            self.domain[1][1] - self.domain[1][0],
-        ];
+    pub fn get_polynomial(&self) -> Polynomial {
+        let coefficients: Vec<[f64; 2]> = self.polynomial[..=self.degree as usize]
+            .iter()
+            .rev()
+            .map(|[re, im, _, _]| [*re as f64, *im as f64])
+            .collect();
+        Polynomial::new(&coefficients).unwrap_or_default()
     }
 
-    pub fn translate(&mut self, vector: [f32; 2]) {
-        let domain_size = self.get_domain_size();
-        self.domain = [
-            [
-                self.domain[0][0] - domain_size[0] * vector[0],
-                self.domain[0][1] - domain_size[0] * vector[0],
-            ],
-            [
-                self.domain[1][0] + domain_size[1] * vector[1],
-                self.domain[1][1] + domain_size[1] * vector[1],
-            ],
-        ];
+    /// Store the coefficients of `polynomial` and its roots found on the CPU.
+    pub fn set_polynomial(&mut self, polynomial: &Polynomial) {
+        let coefficients = polynomial.get_coefficients().iter().rev();
+        let roots = polynomial.get_roots();
+        self.polynomial = [[0.0; 4]; POLYNOMIAL_DEGREE_MAX + 1];
+        for (k, [re, im]) in coefficients.enumerate() {
+            self.polynomial[k][0] = *re as f32;
+            self.polynomial[k][1] = *im as f32;
+        }
+        for (k, [re, im]) in roots.iter().enumerate() {
+            self.polynomial[k][2] = *re as f32;
+            self.polynomial[k][3] = *im as f32;
+        }
+        self.degree = polynomial.get_degree() as u32;
     }
+
+    /// `power` if it is a whole number from 2 to `POWER_INTEGER_MAX`.
+    pub fn get_integer_power(&self) -> Option<u32> {
+        let [re, im] = self.power;
+        let n = re as u32;
+        (im == 0.0 && n as f32 == re && (2..=POWER_INTEGER_MAX).contains(&n)).then_some(n)
+    }
+
+    pub fn get_c(&self) -> [f64; 2] {
+        [
+            self.c[0] as f64 + self.c_lo[0] as f64,
+            self.c[1] as f64 + self.c_lo[1] as f64,
+        ]
+    }
+
+    pub fn set_c(&mut self, c: [f64; 2]) {
+        (self.c[0], self.c_lo[0]) = split_f64(c[0]);
+        (self.c[1], self.c_lo[1]) = split_f64(c[1]);
+    }
+}
+
+/// Split into the closest `f32` and the (also `f32`) remainder.
+pub fn split_f64(value: f64) -> (f32, f32) {
+    let hi = value as f32;
+    (hi, (value - hi as f64) as f32)
 }
@@ -0,0 +1,203 @@
+use crate::bigfloat::BigFloat;
+use crate::uniform::{
+    Uniform, MAX_ITERATIONS_DEFAULT, MAX_ITERATIONS_LIMIT, PRECISION_DOUBLE_SINGLE,
+    PRECISION_PERTURBATION, PRECISION_SINGLE,
+};
+
+/// Below this view size `f32` runs out of precision and the shader
+/// switches to emulated double precision (pairs of `f32`).
+pub const DOUBLE_SINGLE_THRESHOLD: f64 = 0.00001;
+
+/// Below this view size emulated double precision runs out as well and the
+/// shader switches to perturbation around a reference orbit.
+pub const PERTURBATION_THRESHOLD: f64 = 0.0000000000001;
+
+/// Shown when `Viewport::is_beyond_precision`.
+pub const PRECISION_WARNING: &str = "non-integer powers, Newton fractals and custom formulas \
+    are only drawn in single precision, which does not reach this zoom";
+
+/// Deepest zoom allowed, as a power of two of the view size.
+pub const EXPONENT_MIN: i32 = -1000;
+
+/// Largest view size allowed when zooming out.
+pub const SIZE_MAX: f64 = 20.0;
+
+/// Iterations added per halving of the view size by the automatic iteration count.
+const MAX_ITERATIONS_PER_OCTAVE: f64 = 64.0;
+
+/// Relative change of the view size on each zoom step.
+const ZOOM_FACTOR: f64 = 0.025;
+
+/// The part of the complex plane shown in the window.
+///
+/// The center is kept exactly so panning at deep zooms does not drift, the
+/// remaining values are `f64` and never accumulate more than one rounding per step.
+/// Screen positions are given from 0.0 to 1.0, with y pointing down.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub center: [BigFloat; 2],
+    /// Width of the view in the complex plane, the height follows from `aspect_ratio`.
+    pub scale: f64,
+    /// Counterclockwise angle in radians of the screen x axis with respect to the real axis.
+    pub rotation: f64,
+    /// Window width over window height.
+    pub aspect_ratio: f64,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            center: [BigFloat::zero(), BigFloat::zero()],
+            scale: 3.1,
+            rotation: 0.0,
+            aspect_ratio: 1.0,
+        }
+    }
+}
+
+impl Viewport {
+    /// Width and height of the view in the complex plane.
+    pub fn get_size(&self) -> [f64; 2] {
+        [self.scale, self.scale / self.aspect_ratio]
+    }
+
+    pub fn get_center(&self) -> [f64; 2] {
+        [self.center[0].to_f64(), self.center[1].to_f64()]
+    }
+
+    pub fn set_center(&mut self, center: [f64; 2]) {
+        self.center = [BigFloat::from_f64(center[0]), BigFloat::from_f64(center[1])];
+    }
+
+    /// Offset from the center of the point under the screen position `position`.
+    pub fn get_offset(&self, position: [f64; 2]) -> [f64; 2] {
+        let size = self.get_size();
+        self.rotate_vector([(position[0] - 0.5) * size[0], (0.5 - position[1]) * size[1]])
+    }
+
+    /// Point under the screen position `position`, rounded to `f64`.
+    pub fn get_point(&self, position: [f64; 2]) -> [f64; 2] {
+        let center = self.get_center();
+        let offset = self.get_offset(position);
+        [center[0] + offset[0], center[1] + offset[1]]
+    }
+
+    fn rotate_vector(&self, vector: [f64; 2]) -> [f64; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        [
+            vector[0] * cos - vector[1] * sin,
+            vector[0] * sin + vector[1] * cos,
+        ]
+    }
+
+    /// The width is kept when the window changes shape, only the height follows.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        self.aspect_ratio = aspect_ratio;
+    }
+
+    /// Scale the view by `factor` keeping the point under the screen position `anchor` in place.
+    pub fn zoom(&mut self, factor: f64, anchor: [f64; 2]) {
+        if factor < 1.0 && self.get_exponent() <= EXPONENT_MIN {
+            return;
+        }
+        let size = self.get_size();
+        if factor > 1.0 && size[0].max(size[1]) >= SIZE_MAX {
+            return;
+        }
+
+        let offset = self.get_offset(anchor);
+        self.shift([offset[0] * (1.0 - factor), offset[1] * (1.0 - factor)]);
+        self.scale *= factor;
+    }
+
+    pub fn zoom_in(&mut self, anchor: [f64; 2]) {
+        self.zoom(1.0 - ZOOM_FACTOR, anchor);
+    }
+
+    pub fn zoom_out(&mut self, anchor: [f64; 2]) {
+        self.zoom(1.0 + ZOOM_FACTOR, anchor);
+    }
+
+    /// Move the contents of the view along with a screen displacement of `vector`.
+    pub fn translate(&mut self, vector: [f64; 2]) {
+        let size = self.get_size();
+        let offset = self.rotate_vector([vector[0] * size[0], -vector[1] * size[1]]);
+        self.shift([-offset[0], -offset[1]]);
+    }
+
+    /// Rotate the view counterclockwise by `angle` radians around its center.
+    pub fn rotate(&mut self, angle: f64) {
+        self.rotation = (self.rotation + angle).rem_euclid(std::f64::consts::TAU);
+    }
+
+    /// Move the center by `offset`, exactly up to the precision the zoom needs.
+    fn shift(&mut self, offset: [f64; 2]) {
+        let bits = self.get_precision();
+        for (center, offset) in self.center.iter_mut().zip(offset) {
+            *center = center.add(&BigFloat::from_f64(offset)).round(bits);
+        }
+    }
+
+    /// Power of two the shader scales the view by, keeps its size of order one in `f32`.
+    pub fn get_exponent(&self) -> i32 {
+        let size = self.get_size();
+        let exponent = size[0].min(size[1]).log2().floor() as i32;
+        exponent.min(0)
+    }
+
+    /// Bits of precision needed for the exact center at the current zoom.
+    pub fn get_precision(&self) -> u64 {
+        ((64 - self.get_exponent() as i64) as u64).div_ceil(64) * 64
+    }
+
+    /// Number of times the view has been halved in size from the default one.
+    pub fn get_zoom_depth(&self) -> f64 {
+        (Viewport::default().scale / self.scale).log2().max(0.0)
+    }
+
+    /// Iteration count that keeps detail at the current zoom, deeper views need more iterations.
+    pub fn get_max_iterations_auto(&self) -> u32 {
+        let max_iterations =
+            MAX_ITERATIONS_DEFAULT as f64 + MAX_ITERATIONS_PER_OCTAVE * self.get_zoom_depth();
+        (max_iterations as u32).min(MAX_ITERATIONS_LIMIT)
+    }
+
+    /// Cheapest `precision_mode` the shader can render the view with.
+    pub fn get_precision_mode_needed(&self) -> i32 {
+        let size = self.get_size();
+        let size_min = size[0].min(size[1]);
+        if size_min <= PERTURBATION_THRESHOLD {
+            PRECISION_PERTURBATION
+        } else if size_min <= DOUBLE_SINGLE_THRESHOLD {
+            PRECISION_DOUBLE_SINGLE
+        } else {
+            PRECISION_SINGLE
+        }
+    }
+
+    /// Whether the view is too deep for `uniform` to be drawn without the pixels breaking up
+    /// into blocks, as it is only drawn in single precision.
+    pub fn is_beyond_precision(&self, uniform: &Uniform) -> bool {
+        uniform.is_single_precision_only() && self.get_precision_mode_needed() != PRECISION_SINGLE
+    }
+
+    /// Fill in the fields of `uniform` that describe the view.
+    pub fn write_uniform(&self, uniform: &mut Uniform) {
+        let exponent = self.get_exponent();
+        let size = self.get_size();
+        let unit = 2f64.powi(-exponent);
+        uniform.size = [(size[0] * unit) as f32, (size[1] * unit) as f32];
+        uniform.rotation = [self.rotation.cos() as f32, self.rotation.sin() as f32];
+        uniform.exponent = exponent;
+        for (i, center) in self.center.iter().enumerate() {
+            let center_hi = center.to_f64() as f32;
+            let center_lo = center.sub(&BigFloat::from_f64(center_hi as f64));
+            uniform.center[i] = center_hi;
+            uniform.center_lo[i] = center_lo.to_f64() as f32;
+        }
+        uniform.precision_mode = self.get_precision_mode_needed();
+        if uniform.is_single_precision_only() {
+            uniform.precision_mode = PRECISION_SINGLE;
+        }
+    }
+}
@@ -1,17 +1,58 @@
 use winit::{
-    dpi::{LogicalPosition, PhysicalPosition},
+    dpi::{LogicalPosition, LogicalSize, PhysicalPosition},
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::{Icon, WindowBuilder},
+    window::{Fullscreen, Icon, WindowBuilder},
 };
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-use crate::state::State;
+use crate::sampling::Sampling;
+use crate::scene::Scene;
+use crate::state::{State, StateError};
+
+/// How the window starts out, set from the command line.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Logical size of the window, the platform decides when `None`.
+    pub size: Option<[u32; 2]>,
+    pub fullscreen: bool,
+    /// Shown instead of the default Julia set.
+    pub scene: Option<Scene>,
+    pub palette_cycling: bool,
+    /// Redraw only after input changed the view or while it animates, instead of on every
+    /// iteration of the event loop.
+    pub redraw_on_demand: bool,
+    /// Samples averaged in every pixel once the view stays still.
+    pub sampling: Sampling,
+    /// Keep averaging samples past those of `sampling` while the view stays still.
+    pub accumulating: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            size: None,
+            fullscreen: false,
+            scene: None,
+            palette_cycling: true,
+            redraw_on_demand: false,
+            sampling: Sampling::default(),
+            accumulating: true,
+        }
+    }
+}
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    if let Err(error) = run_with(Options::default()).await {
+        log::error!("could not open a window: {}", error);
+    }
+}
+
+/// Open the window and run the event loop, failing only when there is nothing to render with.
+pub async fn run_with(options: Options) -> Result<(), StateError> {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -22,7 +63,14 @@ pub async fn run() {
     }
 
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    let mut window_builder = WindowBuilder::new();
+    if let Some([width, height]) = options.size {
+        window_builder = window_builder.with_inner_size(LogicalSize::new(width, height));
+    }
+    if options.fullscreen {
+        window_builder = window_builder.with_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+    let window = window_builder.build(&event_loop).unwrap();
     let (icon_rgba, icon_width, icon_height) = {
         let icon_bytes = include_bytes!("../public/assets/icon.png");
         let icon_image = image::load_from_memory(icon_bytes).unwrap();
@@ -65,8 +113,15 @@ pub async fn run() {
     }
 
     // State::new uses async code, so we're going to wait for it to finish
-    let mut state = State::new(&window).await;
+    let mut state = State::new(&window).await?;
     state.reset_zoom(); // resize at start
+    if let Some(scene) = options.scene {
+        state.set_scene(scene);
+    }
+    state.palette_cycling = options.palette_cycling;
+    state.sampling = options.sampling;
+    state.accumulating = options.accumulating;
+    window.request_redraw(); // first frame, also when redrawing on demand
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -74,37 +129,56 @@ pub async fn run() {
                 ref event,
                 window_id,
             } if window_id == window.id() => {
-                if !state.input(event) {
-                    match event {
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            input:
-                                KeyboardInput {
-                                    state: ElementState::Pressed,
-                                    virtual_keycode: Some(VirtualKeyCode::Escape),
-                                    ..
-                                },
-                            ..
-                        } => *control_flow = ControlFlow::Exit,
-                        WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
-                        }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            // new_inner_size is &mut so we have to dereference it twice
-                            state.resize(**new_inner_size);
-                        }
-                        WindowEvent::CursorMoved { position, .. } => {
-                            let position: LogicalPosition<f64> =
-                                PhysicalPosition::to_logical(&position, window.scale_factor());
-                            let size = window.inner_size();
+                if let WindowEvent::CursorMoved { position, .. } = event {
+                    let position: LogicalPosition<f64> =
+                        PhysicalPosition::to_logical(position, window.scale_factor());
+                    let size = window.inner_size();
 
-                            let normalized_x = position.x as f32 / size.width as f32;
-                            let normalized_y = position.y as f32 / size.height as f32;
+                    let normalized_x = position.x as f32 / size.width as f32;
+                    let normalized_y = position.y as f32 / size.height as f32;
 
-                            state.uniform.mouse = [normalized_x, normalized_y]; // from 0.0 to 1.0
+                    state.uniform.mouse = [normalized_x, normalized_y]; // from 0.0 to 1.0
+                }
+                if state.input(event) {
+                    window.request_redraw();
+                    return;
+                }
+                match event {
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                        window.request_redraw();
+                    }
+                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        // new_inner_size is &mut so we have to dereference it twice
+                        state.resize(**new_inner_size);
+                        window.request_redraw();
+                    }
+                    WindowEvent::DroppedFile(path) => {
+                        // parameter files and exported images hold views, anything else is a palette
+                        let is_scene = path.extension().is_some_and(|extension| {
+                            extension.eq_ignore_ascii_case("toml")
+                                || extension.eq_ignore_ascii_case("png")
+                        });
+                        if is_scene {
+                            if let Err(error) = state.load_scene(path) {
+                                log::error!("could not load view {}: {}", path.display(), error);
+                            }
+                        } else if let Err(error) = state.load_palette(path) {
+                            log::error!("could not load palette {}: {}", path.display(), error);
                         }
-                        _ => {}
+                        window.request_redraw();
                     }
+                    _ => {}
                 }
             }
             Event::RedrawRequested(window_id) if window_id == window.id() => {
@@ -113,7 +187,8 @@ pub async fn run() {
                     Ok(_) => {}
                     // Reconfigure the surface if it's lost or outdated
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        state.resize(state.size)
+                        state.resize(state.size);
+                        window.request_redraw();
                     }
                     // The system is out of memory, we should probably quit
                     Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
@@ -122,8 +197,14 @@ pub async fn run() {
                 }
             }
             Event::MainEventsCleared => {
-                // RedrawRequested will only trigger once, unless we manually request it.
-                window.request_redraw();
+                if options.redraw_on_demand && !state.is_animating() {
+                    // sleep until input asks for a redraw
+                    *control_flow = ControlFlow::Wait;
+                } else {
+                    // RedrawRequested will only trigger once, unless we manually request it.
+                    *control_flow = ControlFlow::Poll;
+                    window.request_redraw();
+                }
             }
             _ => {}
         }